@@ -0,0 +1,457 @@
+use crate::anomalies::{self, AnomalyConfig};
+use crate::collections;
+use crate::metrics::Metric;
+use crate::regression::{self, Baseline, MetricDelta, Threshold, Verdict};
+use crate::repository::{self, Record, RecordFilter, Scoped};
+use crate::AuditSummary;
+use bson::{oid::ObjectId, Document};
+use chrono::{DateTime, Duration, Utc};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Watches one metric, or the score, for a site, optionally narrowed to a
+/// page and audit profile.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    id: Option<ObjectId>,
+    site_id: ObjectId,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_profile_id: Option<String>,
+    metric: Metric,
+    condition: AlertCondition,
+    /// Minimum time between events with the same deduplication key.
+    cooldown_minutes: i64,
+    channels: Vec<AlertChannel>,
+    enabled: bool,
+}
+
+impl AlertRule {
+    pub fn new(
+        site_id: ObjectId,
+        name: String,
+        metric: Metric,
+        condition: AlertCondition,
+        channels: Vec<AlertChannel>,
+    ) -> AlertRule {
+        AlertRule {
+            id: None,
+            site_id,
+            name,
+            page_id: None,
+            audit_profile_id: None,
+            metric,
+            condition,
+            cooldown_minutes: 24 * 60,
+            channels,
+            enabled: true,
+        }
+    }
+
+    pub fn applies_to(&self, summary: &AuditSummary) -> bool {
+        self.enabled
+            && summary.site_id() == &self.site_id
+            && self
+                .page_id
+                .as_ref()
+                .is_none_or(|id| id == summary.page_id())
+            && self
+                .audit_profile_id
+                .as_ref()
+                .is_none_or(|id| id == summary.audit_profile_id())
+    }
+
+    /// Identifies the problem rather than the run: one rule firing for one
+    /// page and audit profile keeps the same key across runs. Rules without
+    /// an id are told apart by site and name.
+    pub fn dedup_key(&self, summary: &AuditSummary) -> String {
+        let rule = match &self.id {
+            Some(id) => id.to_hex(),
+            None => self.name.clone(),
+        };
+        let key = format!(
+            "{}|{}|{}|{}|{}",
+            self.site_id.to_hex(),
+            rule,
+            summary.page_id(),
+            summary.audit_profile_id(),
+            self.metric.id()
+        );
+        hex::encode(Sha256::digest(key.as_bytes()))
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AlertCondition {
+    /// The value is worse than `threshold`.
+    #[serde(rename_all = "camelCase")]
+    Threshold { threshold: f64 },
+    /// The value regressed significantly against the baseline.
+    #[serde(rename_all = "camelCase")]
+    Regression {
+        baseline: Baseline,
+        threshold: Threshold,
+    },
+    /// The run is an outlier or level shift in the worse direction.
+    #[serde(rename_all = "camelCase")]
+    Anomaly { config: AnomalyConfig },
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum AlertChannel {
+    #[serde(rename_all = "camelCase")]
+    Email { address: String },
+    #[serde(rename_all = "camelCase")]
+    Slack { webhook_url: String },
+    #[serde(rename_all = "camelCase")]
+    Teams { webhook_url: String },
+    #[serde(rename_all = "camelCase")]
+    Webhook { url: String },
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AlertEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_id: Option<ObjectId>,
+    rule_name: String,
+    site_id: ObjectId,
+    page_id: String,
+    audit_profile_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_summary_id: Option<ObjectId>,
+    metric: Metric,
+    value: f64,
+    /// The threshold, baseline or expected value the run was compared with.
+    reference: f64,
+    message: String,
+    dedup_key: String,
+    triggered_at: bson::DateTime,
+    channels: Vec<AlertChannel>,
+}
+
+/// Evaluates the rules against a new summary. `history` holds earlier
+/// summaries and `recent_events` earlier events, which suppress events with
+/// the same deduplication key within the rule's cooldown.
+pub fn evaluate(
+    rules: &[AlertRule],
+    summary: &AuditSummary,
+    history: &[AuditSummary],
+    recent_events: &[AlertEvent],
+    now: DateTime<Utc>,
+) -> Vec<AlertEvent> {
+    rules
+        .iter()
+        .filter(|rule| rule.applies_to(summary))
+        .filter_map(|rule| {
+            let (value, reference) = triggered(rule, summary, history)?;
+            let dedup_key = rule.dedup_key(summary);
+            // A cooldown reaching past the earliest time covers all events.
+            let cooldown_start = Duration::try_minutes(rule.cooldown_minutes)
+                .and_then(|cooldown| now.checked_sub_signed(cooldown))
+                .unwrap_or(DateTime::<Utc>::MIN_UTC);
+            let suppressed = recent_events
+                .iter()
+                .any(|event| event.dedup_key == dedup_key && event.triggered_at.0 > cooldown_start);
+            if suppressed {
+                return None;
+            }
+
+            Some(AlertEvent {
+                id: None,
+                rule_id: rule.id.clone(),
+                rule_name: rule.name.clone(),
+                site_id: summary.site_id().clone(),
+                page_id: summary.page_id().clone(),
+                audit_profile_id: summary.audit_profile_id().clone(),
+                audit_summary_id: summary.id().clone(),
+                metric: rule.metric,
+                value,
+                reference,
+                message: message(rule, summary, value, reference),
+                dedup_key,
+                triggered_at: bson::DateTime(now),
+                channels: rule.channels.clone(),
+            })
+        })
+        .collect()
+}
+
+/// The value and what it was compared with, when the rule fires.
+fn triggered(
+    rule: &AlertRule,
+    summary: &AuditSummary,
+    history: &[AuditSummary],
+) -> Option<(f64, f64)> {
+    let metric = rule.metric;
+    let value = metric.value(summary)?;
+
+    match &rule.condition {
+        AlertCondition::Threshold { threshold } => {
+            let worse = if metric.higher_is_better() {
+                value < *threshold
+            } else {
+                value > *threshold
+            };
+            if worse {
+                Some((value, *threshold))
+            } else {
+                None
+            }
+        }
+        AlertCondition::Regression {
+            baseline,
+            threshold,
+        } => {
+            let baseline_values = regression::select_baseline(summary, history, baseline)?;
+            let baseline_value = *baseline_values.values().get(&metric)?;
+            let delta = MetricDelta::new(metric, baseline_value, value, *threshold);
+            if *delta.verdict() == Verdict::Regressed {
+                Some((value, baseline_value))
+            } else {
+                None
+            }
+        }
+        AlertCondition::Anomaly { config } => {
+            let mut series: Vec<AuditSummary> = history
+                .iter()
+                .filter(|s| {
+                    s.site_id() == summary.site_id()
+                        && s.page_id() == summary.page_id()
+                        && s.audit_profile_id() == summary.audit_profile_id()
+                        && s.fetch_time() < summary.fetch_time()
+                })
+                .cloned()
+                .collect();
+            series.push(summary.clone());
+            let point = anomalies::detect_metric(&series, metric, config).pop()?;
+            match (point.anomaly(), point.direction(), point.expected()) {
+                (Some(_), Some(Verdict::Regressed), Some(expected)) => Some((value, *expected)),
+                _ => None,
+            }
+        }
+    }
+}
+
+fn message(rule: &AlertRule, summary: &AuditSummary, value: f64, reference: f64) -> String {
+    let comparison = match rule.condition {
+        AlertCondition::Threshold { .. } => "threshold",
+        AlertCondition::Regression { .. } => "baseline",
+        AlertCondition::Anomaly { .. } => "expected",
+    };
+    format!(
+        "{}: {} is {} ({} {}) for page {} on {}",
+        rule.name,
+        rule.metric.label(),
+        rule.metric.format(value),
+        comparison,
+        rule.metric.format(reference),
+        summary.page_id(),
+        summary.audit_profile().name()
+    )
+}
+
+impl Record for AlertRule {
+    const COLLECTION: &'static str = collections::ALERT_RULES;
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        repository::scope_matches(self, filter)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        repository::scope_filter_document::<AlertRule>(filter)
+    }
+}
+
+impl Scoped for AlertRule {
+    fn scope(&self) -> (&ObjectId, Option<&str>, Option<&str>) {
+        (
+            &self.site_id,
+            self.page_id.as_deref(),
+            self.audit_profile_id.as_deref(),
+        )
+    }
+}
+
+impl Record for AlertEvent {
+    const COLLECTION: &'static str = collections::ALERT_EVENTS;
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        repository::scope_matches(self, filter)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        repository::scope_filter_document::<AlertEvent>(filter)
+    }
+}
+
+impl Scoped for AlertEvent {
+    fn scope(&self) -> (&ObjectId, Option<&str>, Option<&str>) {
+        (
+            &self.site_id,
+            Some(&self.page_id),
+            Some(&self.audit_profile_id),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::summary;
+    use chrono::TimeZone;
+
+    fn score_rule(site_id: &ObjectId) -> AlertRule {
+        AlertRule::new(
+            site_id.clone(),
+            "Score".to_owned(),
+            Metric::Score,
+            AlertCondition::Threshold { threshold: 0.9 },
+            Vec::new(),
+        )
+    }
+
+    #[test]
+    fn anomaly_series_ignores_other_sites_with_the_same_page_ids() {
+        let site_id = ObjectId::new();
+        let other_site_id = ObjectId::new();
+        let history: Vec<AuditSummary> = [0.95, 0.96, 0.94, 0.95, 0.96, 0.94]
+            .iter()
+            .enumerate()
+            .map(|(day, score)| {
+                summary(
+                    &other_site_id,
+                    &format!("2021-03-0{}T12:00:00Z", day + 1),
+                    *score,
+                )
+            })
+            .collect();
+        let current = summary(&site_id, "2021-03-08T12:00:00Z", 0.6);
+        let rule = AlertRule::new(
+            site_id,
+            "Score drop".to_owned(),
+            Metric::Score,
+            AlertCondition::Anomaly {
+                config: AnomalyConfig::default(),
+            },
+            Vec::new(),
+        );
+        let now = Utc.with_ymd_and_hms(2021, 3, 8, 13, 0, 0).unwrap();
+
+        let events = evaluate(&[rule], &current, &history, &[], now);
+
+        // The site has no history of its own, so nothing is expected yet.
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn cooldown_suppresses_repeats_of_the_same_problem() {
+        let site_id = ObjectId::new();
+        let mut rule = score_rule(&site_id);
+        rule.set_cooldown_minutes(60);
+        let current = summary(&site_id, "2021-03-08T12:00:00Z", 0.6);
+        let now = Utc.with_ymd_and_hms(2021, 3, 8, 13, 0, 0).unwrap();
+        let fired_before = |minutes| {
+            evaluate(
+                &[score_rule(&site_id)],
+                &current,
+                &[],
+                &[],
+                now - Duration::minutes(minutes),
+            )
+        };
+        let (recent, hour_old, year_old) = (
+            fired_before(59),
+            fired_before(60),
+            fired_before(60 * 24 * 365),
+        );
+
+        assert_eq!(evaluate(&[rule.clone()], &current, &[], &[], now).len(), 1);
+        assert!(evaluate(&[rule.clone()], &current, &[], &recent, now).is_empty());
+        assert_eq!(
+            evaluate(&[rule.clone()], &current, &[], &hour_old, now).len(),
+            1
+        );
+
+        rule.set_cooldown_minutes(i64::MAX);
+        assert!(evaluate(&[rule], &current, &[], &year_old, now).is_empty());
+    }
+
+    #[test]
+    fn dedup_keys_of_unsaved_rules_differ_between_sites() {
+        let site_id = ObjectId::new();
+        let other_site_id = ObjectId::new();
+        let current = summary(&site_id, "2021-03-08T12:00:00Z", 0.6);
+
+        assert_eq!(
+            score_rule(&site_id).dedup_key(&current),
+            score_rule(&site_id).dedup_key(&current)
+        );
+        assert_ne!(
+            score_rule(&site_id).dedup_key(&current),
+            score_rule(&other_site_id).dedup_key(&current)
+        );
+    }
+
+    #[test]
+    fn page_scopes_include_site_wide_rules() {
+        let site_id = ObjectId::new();
+        let filter =
+            RecordFilter::for_profile(site_id.clone(), "home".to_owned(), "mobile".to_owned());
+        let mut rule = score_rule(&site_id);
+
+        assert!(rule.matches(&RecordFilter::for_site(site_id.clone())));
+        assert!(rule.matches(&filter));
+        rule.set_page_id(Some("home".to_owned()));
+        assert!(rule.matches(&filter));
+        rule.set_audit_profile_id(Some("mobile".to_owned()));
+        assert!(rule.matches(&filter));
+        rule.set_page_id(Some("blog".to_owned()));
+        assert!(!rule.matches(&filter));
+        assert!(!rule.matches(&RecordFilter::for_site(ObjectId::new())));
+    }
+
+    #[test]
+    fn scope_filters_use_serialized_field_names() {
+        let site_id = ObjectId::new();
+        let filter =
+            RecordFilter::for_profile(site_id.clone(), "home".to_owned(), "mobile".to_owned());
+        let expected = bson::doc! {
+            "siteId": site_id,
+            "pageId": { "$in": ["home", bson::Bson::Null] },
+            "auditProfileId": { "$in": ["mobile", bson::Bson::Null] },
+        };
+
+        assert_eq!(AlertRule::filter_document(&filter), expected);
+        assert_eq!(AlertEvent::filter_document(&filter), expected);
+    }
+}
@@ -0,0 +1,312 @@
+use crate::metrics::{self, Metric};
+use crate::regression::Verdict;
+use crate::AuditSummary;
+use bson::oid::ObjectId;
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+
+/// MAD scaled by this estimates the standard deviation of normal data.
+const MAD_SCALE: f64 = 1.4826;
+
+/// How the expected value and spread are estimated from earlier runs.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Method {
+    /// Median and median absolute deviation of the last `window` runs.
+    #[serde(rename_all = "camelCase")]
+    Mad { window: usize },
+    /// Exponentially weighted mean and variance, weighting the newest run by
+    /// `alpha`.
+    #[serde(rename_all = "camelCase")]
+    Ewma { alpha: f64 },
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, Copy, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AnomalyConfig {
+    method: Method,
+    /// Deviations, in estimated standard deviations, that make a run an
+    /// outlier.
+    threshold: f64,
+    /// Earlier runs needed before a run is scored.
+    min_history: usize,
+    /// Consecutive outliers on the same side that make a level shift.
+    level_shift_runs: usize,
+}
+
+impl AnomalyConfig {
+    pub fn new(method: Method, threshold: f64) -> AnomalyConfig {
+        AnomalyConfig {
+            method,
+            threshold,
+            ..AnomalyConfig::default()
+        }
+    }
+}
+
+impl Default for AnomalyConfig {
+    fn default() -> AnomalyConfig {
+        AnomalyConfig {
+            method: Method::Mad { window: 20 },
+            threshold: 3.5,
+            min_history: 5,
+            level_shift_runs: 3,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AnomalyKind {
+    Outlier,
+    /// The run completing `level_shift_runs` consecutive outliers; later runs
+    /// are compared with the new level.
+    LevelShift,
+}
+
+/// One metric of one run, with how it compares to the runs before it.
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AnnotatedPoint {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_summary_id: Option<ObjectId>,
+    site_id: ObjectId,
+    page_id: String,
+    audit_profile_id: String,
+    fetch_time: String,
+    metric: Metric,
+    value: f64,
+    /// `None` until there is enough history.
+    expected: Option<f64>,
+    deviation: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anomaly: Option<AnomalyKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direction: Option<Verdict>,
+}
+
+impl AnnotatedPoint {
+    pub fn is_anomaly(&self) -> bool {
+        self.anomaly.is_some()
+    }
+}
+
+/// Annotates every metric of a single page and audit profile's history,
+/// grouped by metric and ordered by fetch time.
+pub fn detect(history: &[AuditSummary], config: &AnomalyConfig) -> Vec<AnnotatedPoint> {
+    Metric::all()
+        .iter()
+        .flat_map(|metric| detect_metric(history, *metric, config))
+        .collect()
+}
+
+pub fn detect_metric(
+    history: &[AuditSummary],
+    metric: Metric,
+    config: &AnomalyConfig,
+) -> Vec<AnnotatedPoint> {
+    let mut runs: Vec<(&AuditSummary, f64)> = history
+        .iter()
+        .filter_map(|summary| metric.value(summary).map(|value| (summary, value)))
+        .collect();
+    runs.sort_by(|a, b| a.0.fetch_time().cmp(b.0.fetch_time()));
+
+    let mut points = Vec::with_capacity(runs.len());
+    // Runs that inform the expected value: outliers are left out until they
+    // turn out to be a level shift.
+    let mut reference: Vec<f64> = Vec::new();
+    let mut pending: Vec<f64> = Vec::new();
+    let mut pending_side = 0.0;
+
+    for (summary, value) in runs {
+        let estimate = if reference.len() >= config.min_history {
+            estimate(&reference, config.method)
+        } else {
+            None
+        };
+        let deviation = estimate.map(|(expected, spread)| (value - expected) / spread);
+
+        let mut anomaly = None;
+        match deviation {
+            Some(deviation) if deviation.abs() >= config.threshold => {
+                if deviation.signum() != pending_side {
+                    pending.clear();
+                    pending_side = deviation.signum();
+                }
+                pending.push(value);
+                if pending.len() >= config.level_shift_runs.max(1) {
+                    anomaly = Some(AnomalyKind::LevelShift);
+                    reference = std::mem::take(&mut pending);
+                    pending_side = 0.0;
+                } else {
+                    anomaly = Some(AnomalyKind::Outlier);
+                }
+            }
+            _ => {
+                pending.clear();
+                pending_side = 0.0;
+                reference.push(value);
+            }
+        }
+
+        let direction = anomaly.and(estimate).map(|(expected, _)| {
+            if (value > expected) == metric.higher_is_better() {
+                Verdict::Improved
+            } else {
+                Verdict::Regressed
+            }
+        });
+
+        points.push(AnnotatedPoint {
+            audit_summary_id: summary.id().clone(),
+            site_id: summary.site_id().clone(),
+            page_id: summary.page_id().clone(),
+            audit_profile_id: summary.audit_profile_id().clone(),
+            fetch_time: summary.fetch_time().clone(),
+            metric,
+            value,
+            expected: estimate.map(|(expected, _)| expected),
+            deviation,
+            anomaly,
+            direction,
+        });
+    }
+
+    points
+}
+
+/// Expected value and spread of the reference runs. The spread is kept
+/// above 1% of the expected value so flat histories do not flag every
+/// wobble.
+fn estimate(reference: &[f64], method: Method) -> Option<(f64, f64)> {
+    let (expected, spread) = match method {
+        Method::Mad { window } => {
+            let window = &reference[reference.len().saturating_sub(window.max(1))..];
+            let median = metrics::median(window)?;
+            let deviations: Vec<f64> = window.iter().map(|v| (v - median).abs()).collect();
+            (median, metrics::median(&deviations)? * MAD_SCALE)
+        }
+        Method::Ewma { alpha } => {
+            let (first, rest) = reference.split_first()?;
+            let (mut mean, mut variance) = (*first, 0.0);
+            for value in rest {
+                let difference = value - mean;
+                mean += alpha * difference;
+                variance = (1.0 - alpha) * (variance + alpha * difference * difference);
+            }
+            (mean, variance.sqrt())
+        }
+    };
+
+    Some((
+        expected,
+        spread.max(expected.abs() * 0.01).max(f64::EPSILON),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{set_metric, summary};
+
+    fn history(values: &[f64]) -> Vec<AuditSummary> {
+        let site = ObjectId::new();
+        values
+            .iter()
+            .enumerate()
+            .map(|(day, value)| {
+                let fetch_time = format!("2021-03-{:02}T08:00:00.000Z", day + 1);
+                let mut summary = summary(&site, &fetch_time, 0.9);
+                set_metric(&mut summary, Metric::SpeedIndex, *value);
+                summary
+            })
+            .collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn mad_estimate_of_a_known_series() {
+        let series = [100.0, 102.0, 98.0, 101.0, 99.0, 100.0, 103.0, 97.0];
+
+        // Median 100; absolute deviations 0 0 1 1 2 2 3 3 have median 1.5.
+        let (expected, spread) = estimate(&series, Method::Mad { window: 20 }).unwrap();
+        assert_close(expected, 100.0);
+        assert_close(spread, 1.5 * MAD_SCALE);
+
+        // Only the last three runs: median 100 of 100 103 97, MAD 3.
+        let (expected, spread) = estimate(&series, Method::Mad { window: 3 }).unwrap();
+        assert_close(expected, 100.0);
+        assert_close(spread, 3.0 * MAD_SCALE);
+    }
+
+    #[test]
+    fn ewma_estimate_of_a_known_series() {
+        // mean 10 -> 15 -> 17.5; variance 0 -> 25 -> 18.75.
+        let (expected, spread) =
+            estimate(&[10.0, 20.0, 20.0], Method::Ewma { alpha: 0.5 }).unwrap();
+
+        assert_close(expected, 17.5);
+        assert_close(spread, 18.75_f64.sqrt());
+    }
+
+    #[test]
+    fn flat_history_has_a_minimum_spread() {
+        let (expected, spread) = estimate(&[1000.0; 5], Method::Mad { window: 20 }).unwrap();
+
+        assert_close(expected, 1000.0);
+        assert_close(spread, 10.0);
+        assert_eq!(estimate(&[], Method::Ewma { alpha: 0.5 }), None);
+    }
+
+    #[test]
+    fn outlier_against_mad_baseline() {
+        let series = [100.0, 102.0, 98.0, 101.0, 99.0, 100.0, 103.0, 97.0];
+        let mut values = series.to_vec();
+        values.extend_from_slice(&[104.0, 112.0]);
+        let config = AnomalyConfig::new(Method::Mad { window: 20 }, 3.5);
+
+        let points = detect_metric(&history(&values), Metric::SpeedIndex, &config);
+
+        assert!(points[..5].iter().all(|p| p.expected().is_none()));
+        assert!(points[..9].iter().all(|p| !p.is_anomaly()));
+        let outlier = &points[9];
+        assert_eq!(*outlier.anomaly(), Some(AnomalyKind::Outlier));
+        assert_eq!(*outlier.direction(), Some(Verdict::Regressed));
+        // 104 joined the reference: median 100, MAD 2.
+        assert_eq!(*outlier.expected(), Some(100.0));
+        assert_close(outlier.deviation().unwrap(), 12.0 / (2.0 * MAD_SCALE));
+    }
+
+    #[test]
+    fn consecutive_outliers_become_a_level_shift() {
+        let values = [
+            1000.0, 1000.0, 1000.0, 1000.0, 1000.0, 2000.0, 2000.0, 2000.0, 2000.0,
+        ];
+        let config = AnomalyConfig::new(Method::Mad { window: 20 }, 3.5);
+
+        let points = detect_metric(&history(&values), Metric::SpeedIndex, &config);
+        let anomalies: Vec<Option<AnomalyKind>> = points.iter().map(|p| *p.anomaly()).collect();
+
+        assert_eq!(
+            anomalies[5..],
+            [
+                Some(AnomalyKind::Outlier),
+                Some(AnomalyKind::Outlier),
+                Some(AnomalyKind::LevelShift),
+                None,
+            ]
+        );
+        // The new level replaces the reference, which is too short to score.
+        assert_eq!(*points[8].expected(), None);
+    }
+}
@@ -0,0 +1,716 @@
+use crate::fields::field;
+use crate::lh_models::{Report, Resource};
+use crate::metrics;
+use crate::AuditDetail;
+use bson::{Bson, Document};
+use getset::{Getters, Setters};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// The `ci.assert` section of a `lighthouserc` file.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AssertConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assertions: Option<BTreeMap<String, AssertionSetting>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assert_matrix: Option<Vec<AssertMatrixEntry>>,
+}
+
+impl AssertConfig {
+    pub fn from_json(json: &str) -> Result<AssertConfig, AssertionError> {
+        serde_json::from_str(json).map_err(AssertionError::Json)
+    }
+
+    /// Reads `ci.assert` from a whole `lighthouserc.json`.
+    pub fn from_lighthouserc(json: &str) -> Result<AssertConfig, AssertionError> {
+        let mut rc: serde_json::Value = serde_json::from_str(json).map_err(AssertionError::Json)?;
+        let assert = rc
+            .pointer_mut("/ci/assert")
+            .map(serde_json::Value::take)
+            .unwrap_or_default();
+        if assert.is_null() {
+            return Ok(AssertConfig::default());
+        }
+        serde_json::from_value(assert).map_err(AssertionError::Json)
+    }
+
+    /// The entries applying to a URL: the top level, or the matrix entries
+    /// whose pattern matches.
+    fn entries_for(&self, url: &str) -> Result<Vec<AssertMatrixEntry>, AssertionError> {
+        let matrix = match &self.assert_matrix {
+            Some(matrix) => matrix,
+            None => {
+                return Ok(vec![AssertMatrixEntry {
+                    matching_url_pattern: None,
+                    preset: self.preset.clone(),
+                    assertions: self.assertions.clone(),
+                }])
+            }
+        };
+
+        let mut entries = Vec::new();
+        for entry in matrix {
+            let matches = match &entry.matching_url_pattern {
+                Some(pattern) => Regex::new(pattern)
+                    .map_err(AssertionError::InvalidPattern)?
+                    .is_match(url),
+                None => true,
+            };
+            if matches {
+                entries.push(entry.clone());
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AssertMatrixEntry {
+    /// A regular expression; entries without one match every URL.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    matching_url_pattern: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    preset: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assertions: Option<BTreeMap<String, AssertionSetting>>,
+}
+
+/// `"error"` or `["error", { "minScore": 0.9 }]`.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum AssertionSetting {
+    Level(Level),
+    WithOptions(Level, AssertionOptions),
+}
+
+impl AssertionSetting {
+    pub fn level(&self) -> Level {
+        match self {
+            AssertionSetting::Level(level) | AssertionSetting::WithOptions(level, _) => *level,
+        }
+    }
+
+    pub fn options(&self) -> AssertionOptions {
+        match self {
+            AssertionSetting::Level(_) => AssertionOptions::default(),
+            AssertionSetting::WithOptions(_, options) => options.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Off,
+    Warn,
+    Error,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_length: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_numeric_value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    min_numeric_value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aggregation_method: Option<AggregationMethod>,
+}
+
+impl AssertionOptions {
+    fn new(min_score: Option<f64>, max_length: Option<f64>) -> AssertionOptions {
+        AssertionOptions {
+            min_score,
+            max_length,
+            ..AssertionOptions::default()
+        }
+    }
+}
+
+/// How values from several runs of a URL are combined.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum AggregationMethod {
+    Median,
+    /// Lighthouse CI's default.
+    #[default]
+    Optimistic,
+    Pessimistic,
+    /// Values of the run with the median performance score.
+    MedianRun,
+}
+
+#[derive(Debug)]
+pub enum AssertionError {
+    Json(serde_json::Error),
+    InvalidPattern(regex::Error),
+    UnknownPreset(String),
+}
+
+impl fmt::Display for AssertionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssertionError::Json(e) => write!(f, "invalid assertion config: {}", e),
+            AssertionError::InvalidPattern(e) => write!(f, "invalid matchingUrlPattern: {}", e),
+            AssertionError::UnknownPreset(preset) => write!(f, "unknown preset {}", preset),
+        }
+    }
+}
+
+impl std::error::Error for AssertionError {}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum AssertionStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResult {
+    url: String,
+    /// The assertion key, e.g. `categories:performance`.
+    name: String,
+    level: Level,
+    /// The option checked, e.g. `minScore`.
+    operator: String,
+    expected: f64,
+    /// `None` when the audit is missing from the runs.
+    actual: Option<f64>,
+    values: Vec<f64>,
+    status: AssertionStatus,
+}
+
+/// Values an assertion can check for one audit of one run.
+#[derive(Debug, Getters, Default, Clone, Copy, PartialEq)]
+#[getset(get = "pub")]
+pub struct AuditValues {
+    score: Option<f64>,
+    numeric_value: Option<f64>,
+    /// Number of `details.items`.
+    length: Option<f64>,
+}
+
+impl AuditValues {
+    pub fn new(score: Option<f64>, numeric_value: Option<f64>, length: Option<f64>) -> AuditValues {
+        AuditValues {
+            score,
+            numeric_value,
+            length,
+        }
+    }
+}
+
+/// A Lighthouse run that assertions can be checked against.
+pub trait AssertionTarget {
+    fn url(&self) -> &str;
+
+    fn performance_score(&self) -> Option<f64>;
+
+    /// Audits by Lighthouse id.
+    fn audit_values(&self) -> BTreeMap<String, AuditValues>;
+
+    fn resources(&self) -> Option<&[Resource]>;
+}
+
+impl AssertionTarget for Report {
+    fn url(&self) -> &str {
+        self.final_url()
+    }
+
+    fn performance_score(&self) -> Option<f64> {
+        Some(*self.categories().performance().score())
+    }
+
+    fn audit_values(&self) -> BTreeMap<String, AuditValues> {
+        bson::to_document(self.audits())
+            .map(|document| document_audits(&document))
+            .unwrap_or_default()
+    }
+
+    fn resources(&self) -> Option<&[Resource]> {
+        Some(self.audits().resource_summary().details().items())
+    }
+}
+
+impl AssertionTarget for AuditDetail {
+    fn url(&self) -> &str {
+        self.final_url()
+    }
+
+    fn performance_score(&self) -> Option<f64> {
+        Some(*self.categories().performance().score())
+    }
+
+    fn audit_values(&self) -> BTreeMap<String, AuditValues> {
+        let document = match bson::to_document(self) {
+            Ok(document) => document,
+            Err(_) => return BTreeMap::new(),
+        };
+        let mut audits = document_audits(&document);
+        if let Ok(web_vitals) = document.get_document(field::<AuditDetail>("web_vitals")) {
+            audits.extend(document_audits(web_vitals));
+        }
+        audits
+    }
+
+    fn resources(&self) -> Option<&[Resource]> {
+        self.resource_summary()
+            .as_ref()
+            .map(|audit| audit.details().items().as_slice())
+    }
+}
+
+/// Collects the audits held directly in a document, recognised by their
+/// `id` and `title` fields.
+fn document_audits(document: &Document) -> BTreeMap<String, AuditValues> {
+    document
+        .values()
+        .filter_map(|value| match value {
+            Bson::Document(audit) => Some(audit),
+            _ => None,
+        })
+        .filter_map(
+            |audit| match (audit.get_str("id"), audit.contains_key("title")) {
+                (Ok(id), true) => Some((id.to_owned(), audit)),
+                _ => None,
+            },
+        )
+        .map(|(id, audit)| {
+            let length = audit
+                .get_document("details")
+                .and_then(|details| details.get_array("items"))
+                .map(|items| items.len() as f64)
+                .ok();
+            let values = AuditValues {
+                score: number(audit.get("score")),
+                numeric_value: number(audit.get("numericValue")),
+                length,
+            };
+            (id, values)
+        })
+        .collect()
+}
+
+fn number(value: Option<&Bson>) -> Option<f64> {
+    match value? {
+        Bson::Double(value) => Some(*value),
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+/// Assertions of a preset, restricted to the audits these models keep.
+/// `lighthouse:no-pwa` equals `lighthouse:recommended` here, as no PWA
+/// audits are kept.
+pub fn preset(name: &str) -> Result<BTreeMap<String, AssertionSetting>, AssertionError> {
+    let metrics = [
+        "first-contentful-paint",
+        "first-meaningful-paint",
+        "largest-contentful-paint",
+        "speed-index",
+        "interactive",
+        "first-cpu-idle",
+        "max-potential-fid",
+        "total-blocking-time",
+        "cumulative-layout-shift",
+    ];
+    let opportunities = [
+        "uses-responsive-images",
+        "uses-optimized-images",
+        "uses-webp-images",
+        "offscreen-images",
+        "uses-http2",
+        "unminified-css",
+        "unminified-javascript",
+        "unused-css-rules",
+        "unused-javascript",
+        "render-blocking-resources",
+        "uses-long-cache-ttl",
+        "uses-rel-preconnect",
+    ];
+    let diagnostics = [
+        "server-response-time",
+        "bootup-time",
+        "mainthread-work-breakdown",
+    ];
+
+    let (metric, opportunity, diagnostic) = match name {
+        "lighthouse:all" => {
+            let error =
+                AssertionSetting::WithOptions(Level::Error, AssertionOptions::new(Some(1.0), None));
+            (error.clone(), error.clone(), error)
+        }
+        "lighthouse:recommended" | "lighthouse:no-pwa" => (
+            AssertionSetting::WithOptions(Level::Warn, AssertionOptions::new(Some(0.9), None)),
+            AssertionSetting::WithOptions(Level::Warn, AssertionOptions::new(None, Some(0.0))),
+            AssertionSetting::WithOptions(Level::Warn, AssertionOptions::new(Some(0.9), None)),
+        ),
+        _ => return Err(AssertionError::UnknownPreset(name.to_owned())),
+    };
+
+    let mut assertions = BTreeMap::new();
+    for (ids, setting) in [
+        (&metrics[..], metric),
+        (&opportunities[..], opportunity),
+        (&diagnostics[..], diagnostic),
+    ]
+    .iter()
+    {
+        for id in ids.iter() {
+            assertions.insert((*id).to_owned(), setting.clone());
+        }
+    }
+
+    Ok(assertions)
+}
+
+enum AssertionKey<'a> {
+    Category(&'a str),
+    Audit(&'a str),
+    /// `resource-summary:<type>:size` or `resource-summary:<type>:count`.
+    Resource(&'a str, &'a str),
+}
+
+impl<'a> AssertionKey<'a> {
+    fn parse(key: &'a str) -> AssertionKey<'a> {
+        let parts: Vec<&str> = key.split(':').collect();
+        match parts.as_slice() {
+            ["categories", category] => AssertionKey::Category(category),
+            ["resource-summary", resource_type, property] => {
+                AssertionKey::Resource(resource_type, property)
+            }
+            _ => AssertionKey::Audit(key),
+        }
+    }
+
+    fn values<T: AssertionTarget>(
+        &self,
+        target: &T,
+        audits: &BTreeMap<String, AuditValues>,
+    ) -> Option<AuditValues> {
+        match self {
+            AssertionKey::Category(category) if *category == "performance" => Some(AuditValues {
+                score: target.performance_score(),
+                ..AuditValues::default()
+            }),
+            AssertionKey::Category(_) => None,
+            AssertionKey::Audit(id) => audits.get(*id).copied(),
+            AssertionKey::Resource(resource_type, property) => {
+                let resource = target
+                    .resources()?
+                    .iter()
+                    .find(|r| r.resource_type() == resource_type)?;
+                let numeric_value = match *property {
+                    "size" => resource.transfer_size().unwrap_or(0) as f64,
+                    "count" => *resource.request_count() as f64,
+                    _ => return None,
+                };
+                Some(AuditValues {
+                    numeric_value: Some(numeric_value),
+                    ..AuditValues::default()
+                })
+            }
+        }
+    }
+}
+
+/// Checks the runs of one URL. Assertions from a preset are skipped for
+/// audits missing from the runs; explicit assertions on missing audits fail.
+pub fn evaluate<T: AssertionTarget>(
+    config: &AssertConfig,
+    runs: &[T],
+) -> Result<Vec<AssertionResult>, AssertionError> {
+    let url = match runs.first() {
+        Some(run) => run.url().to_owned(),
+        None => return Ok(Vec::new()),
+    };
+    let audits: Vec<BTreeMap<String, AuditValues>> =
+        runs.iter().map(|run| run.audit_values()).collect();
+    let median_run = median_run(runs);
+
+    let mut results = Vec::new();
+    for entry in config.entries_for(&url)? {
+        let mut assertions = match &entry.preset {
+            Some(name) => preset(name)?,
+            None => BTreeMap::new(),
+        };
+        let explicit: BTreeSet<String> = entry
+            .assertions
+            .iter()
+            .flat_map(|assertions| assertions.keys().cloned())
+            .collect();
+        assertions.extend(entry.assertions.clone().unwrap_or_default());
+
+        for (name, setting) in assertions {
+            if setting.level() == Level::Off {
+                continue;
+            }
+            let key = AssertionKey::parse(&name);
+            let values: Vec<Option<AuditValues>> = runs
+                .iter()
+                .zip(audits.iter())
+                .map(|(run, audits)| key.values(run, audits))
+                .collect();
+            let audit_present = values.iter().any(Option::is_some);
+            if !audit_present && !explicit.contains(&name) {
+                continue;
+            }
+
+            let options = setting.options();
+            let method = options.aggregation_method.unwrap_or_default();
+            let mut checks = Vec::new();
+            if let Some(min_score) = options.min_score {
+                checks.push((
+                    "minScore",
+                    min_score,
+                    Check::Min,
+                    pick(&values, |v| v.score),
+                ));
+            }
+            if let Some(max_length) = options.max_length {
+                checks.push((
+                    "maxLength",
+                    max_length,
+                    Check::Max,
+                    pick(&values, |v| v.length),
+                ));
+            }
+            if let Some(max) = options.max_numeric_value {
+                checks.push((
+                    "maxNumericValue",
+                    max,
+                    Check::Max,
+                    pick(&values, |v| v.numeric_value),
+                ));
+            }
+            if let Some(min) = options.min_numeric_value {
+                checks.push((
+                    "minNumericValue",
+                    min,
+                    Check::Min,
+                    pick(&values, |v| v.numeric_value),
+                ));
+            }
+            if checks.is_empty() {
+                checks.push(("minScore", 1.0, Check::Min, pick(&values, |v| v.score)));
+            }
+
+            for (operator, expected, check, values) in checks {
+                let actual = aggregate(&values, check, method, median_run);
+                let present: Vec<f64> = values.iter().flatten().copied().collect();
+                let passed = match actual {
+                    Some(actual) => check.passes(actual, expected),
+                    // Audits without a score are informative or not applicable.
+                    None => audit_present && operator == "minScore",
+                };
+                let status = match (passed, setting.level()) {
+                    (true, _) => AssertionStatus::Pass,
+                    (false, Level::Warn) => AssertionStatus::Warn,
+                    (false, _) => AssertionStatus::Fail,
+                };
+
+                results.push(AssertionResult {
+                    url: url.clone(),
+                    name: name.clone(),
+                    level: setting.level(),
+                    operator: operator.to_owned(),
+                    expected,
+                    actual,
+                    values: present,
+                    status,
+                });
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Check {
+    Min,
+    Max,
+}
+
+impl Check {
+    fn passes(&self, actual: f64, expected: f64) -> bool {
+        match self {
+            Check::Min => actual >= expected,
+            Check::Max => actual <= expected,
+        }
+    }
+}
+
+fn pick<F: Fn(&AuditValues) -> Option<f64>>(
+    values: &[Option<AuditValues>],
+    property: F,
+) -> Vec<Option<f64>> {
+    values
+        .iter()
+        .map(|values| values.as_ref().and_then(&property))
+        .collect()
+}
+
+/// Combines per-run values; optimistic takes the passing side of the
+/// check, pessimistic the failing side.
+fn aggregate(
+    values: &[Option<f64>],
+    check: Check,
+    method: AggregationMethod,
+    median_run: Option<usize>,
+) -> Option<f64> {
+    let present: Vec<f64> = values.iter().flatten().copied().collect();
+    let best = |a: f64, b: f64| match check {
+        Check::Min => a.max(b),
+        Check::Max => a.min(b),
+    };
+    let worst = |a: f64, b: f64| match check {
+        Check::Min => a.min(b),
+        Check::Max => a.max(b),
+    };
+
+    match method {
+        AggregationMethod::Median => metrics::median(&present),
+        AggregationMethod::Optimistic => present.into_iter().reduce(best),
+        AggregationMethod::Pessimistic => present.into_iter().reduce(worst),
+        AggregationMethod::MedianRun => {
+            median_run.and_then(|index| values.get(index).copied().flatten())
+        }
+    }
+}
+
+/// Index of the run whose performance score is the median.
+fn median_run<T: AssertionTarget>(runs: &[T]) -> Option<usize> {
+    let mut scores: Vec<(usize, f64)> = runs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, run)| run.performance_score().map(|score| (index, score)))
+        .collect();
+    scores.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    scores.get(scores.len() / 2).map(|(index, _)| *index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Run {
+        score: f64,
+        fcp: f64,
+    }
+
+    impl AssertionTarget for Run {
+        fn url(&self) -> &str {
+            "https://example.com/"
+        }
+
+        fn performance_score(&self) -> Option<f64> {
+            Some(self.score)
+        }
+
+        fn audit_values(&self) -> BTreeMap<String, AuditValues> {
+            let mut audits = BTreeMap::new();
+            audits.insert(
+                "first-contentful-paint".to_owned(),
+                AuditValues {
+                    score: Some(0.9),
+                    numeric_value: Some(self.fcp),
+                    length: None,
+                },
+            );
+            audits
+        }
+
+        fn resources(&self) -> Option<&[Resource]> {
+            None
+        }
+    }
+
+    fn result<'a>(results: &'a [AssertionResult], name: &str) -> &'a AssertionResult {
+        results.iter().find(|r| r.name() == name).unwrap()
+    }
+
+    #[test]
+    fn reads_the_assert_section_of_a_lighthouserc() {
+        let config =
+            AssertConfig::from_lighthouserc(include_str!("../tests/fixtures/lighthouserc.json"))
+                .unwrap();
+
+        assert_eq!(config.preset().as_deref(), Some("lighthouse:recommended"));
+        let assertions = config.assertions().as_ref().unwrap();
+        assert_eq!(assertions.len(), 4);
+        assert_eq!(
+            assertions["uses-long-cache-ttl"],
+            AssertionSetting::Level(Level::Off)
+        );
+        let fcp = assertions["first-contentful-paint"].options();
+        assert_eq!(fcp.max_numeric_value(), &Some(2000.0));
+        assert_eq!(
+            fcp.aggregation_method(),
+            &Some(AggregationMethod::Pessimistic)
+        );
+        assert_eq!(
+            assertions["categories:performance"]
+                .options()
+                .aggregation_method(),
+            &None
+        );
+    }
+
+    #[test]
+    fn aggregates_optimistically_unless_configured_otherwise() {
+        let config =
+            AssertConfig::from_lighthouserc(include_str!("../tests/fixtures/lighthouserc.json"))
+                .unwrap();
+        let runs = [
+            Run {
+                score: 0.85,
+                fcp: 1900.0,
+            },
+            Run {
+                score: 0.95,
+                fcp: 2100.0,
+            },
+            Run {
+                score: 0.8,
+                fcp: 1800.0,
+            },
+        ];
+
+        let results = evaluate(&config, &runs).unwrap();
+
+        let performance = result(&results, "categories:performance");
+        assert_eq!(performance.actual(), &Some(0.95));
+        assert_eq!(performance.status(), &AssertionStatus::Pass);
+        let fcp = results
+            .iter()
+            .find(|r| r.name() == "first-contentful-paint" && r.operator() == "maxNumericValue")
+            .unwrap();
+        assert_eq!(fcp.actual(), &Some(2100.0));
+        assert_eq!(fcp.status(), &AssertionStatus::Warn);
+        assert_eq!(
+            result(&results, "resource-summary:script:size").status(),
+            &AssertionStatus::Fail
+        );
+        assert!(results.iter().all(|r| r.name() != "uses-long-cache-ttl"));
+    }
+}
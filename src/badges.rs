@@ -0,0 +1,186 @@
+use crate::format::escape_html;
+use crate::metrics::{Metric, Rating};
+use crate::{AuditSummary, WebVitals};
+use bson::oid::ObjectId;
+use std::collections::BTreeMap;
+
+const LABEL_COLOR: &str = "#555";
+
+/// A shields-style badge for a performance score in 0..=1, coloured by
+/// Lighthouse's 0–49, 50–89 and 90–100 bands.
+pub fn score_badge(score: f64) -> String {
+    let rounded = (score * 100.0).round().clamp(0.0, 100.0);
+    badge(
+        "performance",
+        &format!("{:.0}", rounded),
+        score_rating(rounded).color(),
+    )
+}
+
+pub fn summary_badge(summary: &AuditSummary) -> String {
+    score_badge(*summary.categories().performance().score())
+}
+
+/// A badge for one metric, e.g. `LCP | 2.1 s`; `None` for the score or a
+/// metric the run did not report.
+pub fn metric_badge(metric: Metric, web_vitals: &WebVitals) -> Option<String> {
+    let value = metric.web_vital_value(web_vitals)?;
+    Some(badge(
+        metric.abbreviation(),
+        &metric.format(value),
+        metric.rating(value).color(),
+    ))
+}
+
+/// A badge for the mean of the latest score of every site, page and audit
+/// profile among the summaries; `None` without summaries.
+pub fn site_average_badge(summaries: &[AuditSummary]) -> Option<String> {
+    let mut latest: BTreeMap<(ObjectId, &str, &str), &AuditSummary> = BTreeMap::new();
+    for summary in summaries {
+        let key = (
+            summary.site_id().clone(),
+            summary.page_id().as_str(),
+            summary.audit_profile_id().as_str(),
+        );
+        let newer = latest
+            .get(&key)
+            .is_none_or(|current| summary.fetch_time() > current.fetch_time());
+        if newer {
+            latest.insert(key, summary);
+        }
+    }
+    if latest.is_empty() {
+        return None;
+    }
+
+    let total: f64 = latest
+        .values()
+        .map(|summary| *summary.categories().performance().score())
+        .sum();
+    let rounded = (total / latest.len() as f64 * 100.0)
+        .round()
+        .clamp(0.0, 100.0);
+    Some(badge(
+        "site performance",
+        &format!("{:.0}", rounded),
+        score_rating(rounded).color(),
+    ))
+}
+
+/// Renders a flat badge with the label on grey and the message on `color`.
+pub fn badge(label: &str, message: &str, color: &str) -> String {
+    let label_width = (text_width(label) + 10.0).round();
+    let message_width = (text_width(message) + 10.0).round();
+    let width = label_width + message_width;
+    let title = escape_html(&format!("{}: {}", label, message));
+    let label = escape_html(label);
+    let message = escape_html(message);
+    let color = escape_html(color);
+
+    format!(
+        concat!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"20\" role=\"img\" aria-label=\"{title}\">",
+            "<title>{title}</title>",
+            "<linearGradient id=\"s\" x2=\"0\" y2=\"100%\"><stop offset=\"0\" stop-color=\"#bbb\" stop-opacity=\".1\"/><stop offset=\"1\" stop-opacity=\".1\"/></linearGradient>",
+            "<clipPath id=\"r\"><rect width=\"{width}\" height=\"20\" rx=\"3\" fill=\"#fff\"/></clipPath>",
+            "<g clip-path=\"url(#r)\">",
+            "<rect width=\"{label_width}\" height=\"20\" fill=\"{label_color}\"/>",
+            "<rect x=\"{label_width}\" width=\"{message_width}\" height=\"20\" fill=\"{color}\"/>",
+            "<rect width=\"{width}\" height=\"20\" fill=\"url(#s)\"/>",
+            "</g>",
+            "<g fill=\"#fff\" text-anchor=\"middle\" font-family=\"Verdana,Geneva,DejaVu Sans,sans-serif\" font-size=\"11\">",
+            "<text x=\"{label_x}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{label}</text>",
+            "<text x=\"{label_x}\" y=\"14\">{label}</text>",
+            "<text x=\"{message_x}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{message}</text>",
+            "<text x=\"{message_x}\" y=\"14\">{message}</text>",
+            "</g></svg>"
+        ),
+        width = width,
+        title = title,
+        label_width = label_width,
+        message_width = message_width,
+        label_color = LABEL_COLOR,
+        color = color,
+        label_x = label_width / 2.0,
+        message_x = label_width + message_width / 2.0,
+        label = label,
+        message = message,
+    )
+}
+
+/// Rating of a score in 0..=100.
+fn score_rating(score: f64) -> Rating {
+    if score >= 90.0 {
+        Rating::Good
+    } else if score >= 50.0 {
+        Rating::NeedsImprovement
+    } else {
+        Rating::Poor
+    }
+}
+
+/// Approximate width of text in 11px Verdana, which badges are sized for.
+fn text_width(text: &str) -> f64 {
+    text.chars()
+        .map(|c| match c {
+            'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' => 3.5,
+            ' ' | 'f' | 'r' | 't' | '(' | ')' | '[' | ']' | '-' => 4.5,
+            'm' | 'w' | 'M' | 'W' | '%' => 10.0,
+            c if c.is_ascii_uppercase() => 7.5,
+            c if c.is_ascii_digit() => 7.0,
+            _ => 6.5,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{audit_summary, summary};
+
+    fn snapshot(svg: &str) -> &str {
+        svg.trim_end()
+    }
+
+    #[test]
+    fn score_badge_matches_snapshot() {
+        assert_eq!(
+            score_badge(0.93),
+            snapshot(include_str!("../tests/fixtures/badges/score.svg"))
+        );
+        assert_eq!(summary_badge(&audit_summary()), score_badge(0.86));
+    }
+
+    #[test]
+    fn metric_badge_matches_snapshot() {
+        let web_vitals = audit_summary().web_vitals().clone();
+
+        assert_eq!(
+            metric_badge(Metric::LargestContentfulPaint, &web_vitals).unwrap(),
+            snapshot(include_str!("../tests/fixtures/badges/lcp.svg"))
+        );
+        assert_eq!(metric_badge(Metric::Score, &web_vitals), None);
+    }
+
+    #[test]
+    fn site_average_uses_the_latest_run_of_every_site_page_and_profile() {
+        let site_id = ObjectId::new();
+        let other_site_id = ObjectId::new();
+        let mut blog = summary(&site_id, "2021-03-01T12:00:00Z", 0.4);
+        blog.set_page_id("blog".to_owned());
+        let summaries = vec![
+            summary(&site_id, "2021-03-01T12:00:00Z", 0.2),
+            summary(&site_id, "2021-03-02T12:00:00Z", 0.9),
+            blog,
+            // Same page and profile ids on another site.
+            summary(&other_site_id, "2021-03-01T12:00:00Z", 0.5),
+        ];
+
+        // (0.9 + 0.4 + 0.5) / 3 = 0.6.
+        assert_eq!(
+            site_average_badge(&summaries).unwrap(),
+            snapshot(include_str!("../tests/fixtures/badges/site_average.svg"))
+        );
+        assert_eq!(site_average_badge(&[]), None);
+    }
+}
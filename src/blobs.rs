@@ -0,0 +1,393 @@
+pub mod filesystem;
+pub mod memory;
+
+pub use filesystem::FileSystemBlobStore;
+pub use memory::InMemoryBlobStore;
+
+use crate::AuditDetail;
+use bson::{oid::ObjectId, spec::BinarySubtype, Binary};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+
+/// GridFS's default chunk size, 255 KiB.
+pub const DEFAULT_CHUNK_SIZE: i32 = 255 * 1024;
+
+/// Points at content held in a `BlobStore`, addressed by its SHA-256 hash.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct BlobRef {
+    hash: String,
+    length: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+}
+
+impl BlobRef {
+    pub fn new(hash: String, length: i64, content_type: Option<String>) -> BlobRef {
+        BlobRef {
+            hash,
+            length,
+            content_type,
+        }
+    }
+}
+
+pub trait BlobStore {
+    /// Stores the content under its hash; storing the same bytes twice is a
+    /// no-op.
+    fn put(&self, data: &[u8]) -> Result<String, BlobError>;
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, BlobError>;
+
+    /// Removes the blob outright. Blobs are shared by every detail with the
+    /// same content and are not reference-counted, so this is for garbage
+    /// collection only: delete a hash only after checking that no stored
+    /// detail refers to it, e.g. with `referenced_blobs`.
+    fn delete(&self, hash: &str) -> Result<bool, BlobError>;
+
+    fn contains(&self, hash: &str) -> Result<bool, BlobError> {
+        Ok(self.get(hash)?.is_some())
+    }
+}
+
+#[derive(Debug)]
+pub enum BlobError {
+    Io(io::Error),
+    InvalidData(String),
+    Missing(String),
+    HashMismatch { expected: String, actual: String },
+}
+
+impl fmt::Display for BlobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlobError::Io(e) => write!(f, "blob store error: {}", e),
+            BlobError::InvalidData(message) => write!(f, "invalid blob data: {}", message),
+            BlobError::Missing(hash) => write!(f, "blob {} not found", hash),
+            BlobError::HashMismatch { expected, actual } => {
+                write!(f, "blob {} has content hashing to {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+impl From<io::Error> for BlobError {
+    fn from(e: io::Error) -> BlobError {
+        BlobError::Io(e)
+    }
+}
+
+pub fn content_hash(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// A `fs.files` document.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct GridFsFile {
+    #[serde(rename = "_id")]
+    id: String,
+    length: i64,
+    chunk_size: i32,
+    upload_date: bson::DateTime,
+    filename: String,
+}
+
+/// A `fs.chunks` document.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
+#[getset(get = "pub", set = "pub")]
+pub struct GridFsChunk {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    files_id: String,
+    n: i32,
+    data: Binary,
+}
+
+/// Lays content out as GridFS file and chunk documents, using the content
+/// hash as the file id so identical blobs share one file.
+pub fn to_gridfs(data: &[u8], chunk_size: i32) -> (GridFsFile, Vec<GridFsChunk>) {
+    let hash = content_hash(data);
+    let file = GridFsFile {
+        id: hash.clone(),
+        length: data.len() as i64,
+        chunk_size,
+        upload_date: bson::DateTime(chrono::Utc::now()),
+        filename: hash.clone(),
+    };
+    let chunks = data
+        .chunks(chunk_size.max(1) as usize)
+        .enumerate()
+        .map(|(n, bytes)| GridFsChunk {
+            id: ObjectId::new(),
+            files_id: hash.clone(),
+            n: n as i32,
+            data: Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: bytes.to_vec(),
+            },
+        })
+        .collect();
+
+    (file, chunks)
+}
+
+pub fn from_gridfs(file: &GridFsFile, chunks: &[GridFsChunk]) -> Result<Vec<u8>, BlobError> {
+    let mut chunks: Vec<&GridFsChunk> = chunks.iter().filter(|c| c.files_id == file.id).collect();
+    chunks.sort_by_key(|c| c.n);
+
+    let mut data = Vec::with_capacity(file.length as usize);
+    for (n, chunk) in chunks.iter().enumerate() {
+        if chunk.n != n as i32 {
+            return Err(BlobError::InvalidData(format!(
+                "chunk {} of {} is missing",
+                n, file.id
+            )));
+        }
+        data.extend_from_slice(&chunk.data.bytes);
+    }
+    if data.len() as i64 != file.length {
+        return Err(BlobError::InvalidData(format!(
+            "{} has {} bytes, expected {}",
+            file.id,
+            data.len(),
+            file.length
+        )));
+    }
+
+    Ok(data)
+}
+
+/// Moves inline filmstrip frames into the store, leaving a `BlobRef` on each
+/// frame. Returns the number of frames moved.
+pub fn externalise_filmstrip<S: BlobStore>(
+    detail: &mut AuditDetail,
+    store: &S,
+) -> Result<usize, BlobError> {
+    let items = match detail.screenshot_thumbnails.as_mut() {
+        Some(audit) => audit.details_mut().items_mut(),
+        None => return Ok(0),
+    };
+
+    let mut count = 0;
+    for item in items.iter_mut() {
+        if item.data().is_empty() {
+            continue;
+        }
+
+        let (content_type, bytes) = decode_data_url(item.data())?;
+        let hash = store.put(&bytes)?;
+        item.set_blob(Some(BlobRef::new(hash, bytes.len() as i64, content_type)));
+        item.set_data(String::new());
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Hashes of the blobs the details' filmstrips refer to. A blob store's
+/// garbage collection should keep every hash returned for the stored details.
+pub fn referenced_blobs<'a>(
+    details: impl IntoIterator<Item = &'a AuditDetail>,
+) -> BTreeSet<String> {
+    details
+        .into_iter()
+        .filter_map(|detail| detail.screenshot_thumbnails().as_ref())
+        .flat_map(|audit| audit.details().items())
+        .filter_map(|item| item.blob().as_ref())
+        .map(|blob| blob.hash().clone())
+        .collect()
+}
+
+/// Restores inline data for frames held in the store, e.g. before display.
+/// Content that no longer hashes to the stored hash is an error rather than
+/// a wrong frame. Returns the number of frames restored.
+pub fn hydrate_filmstrip<S: BlobStore>(
+    detail: &mut AuditDetail,
+    store: &S,
+) -> Result<usize, BlobError> {
+    let items = match detail.screenshot_thumbnails.as_mut() {
+        Some(audit) => audit.details_mut().items_mut(),
+        None => return Ok(0),
+    };
+
+    let mut count = 0;
+    for item in items.iter_mut() {
+        let blob = match item.blob() {
+            Some(blob) => blob.clone(),
+            None => continue,
+        };
+
+        let bytes = store
+            .get(blob.hash())?
+            .ok_or_else(|| BlobError::Missing(blob.hash().clone()))?;
+        let actual = content_hash(&bytes);
+        if &actual != blob.hash() {
+            return Err(BlobError::HashMismatch {
+                expected: blob.hash().clone(),
+                actual,
+            });
+        }
+        item.set_data(encode_data_url(blob.content_type().as_deref(), &bytes));
+        item.set_blob(None);
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Splits `data:image/jpeg;base64,...` into its content type and bytes.
+/// Bare base64 is accepted and has no content type.
+fn decode_data_url(data: &str) -> Result<(Option<String>, Vec<u8>), BlobError> {
+    let (content_type, encoded) = match data.strip_prefix("data:") {
+        Some(url) => {
+            let (header, encoded) = url
+                .split_once(',')
+                .ok_or_else(|| BlobError::InvalidData("data url has no payload".to_owned()))?;
+            let content_type = header.trim_end_matches(";base64");
+            (Some(content_type.to_owned()), encoded)
+        }
+        None => (None, data),
+    };
+
+    let bytes = base64::decode(encoded).map_err(|e| BlobError::InvalidData(e.to_string()))?;
+    Ok((content_type, bytes))
+}
+
+fn encode_data_url(content_type: Option<&str>, bytes: &[u8]) -> String {
+    match content_type {
+        Some(content_type) => format!("data:{};base64,{}", content_type, base64::encode(bytes)),
+        None => base64::encode(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lh_models::{Audit, Filmstrip, FilmstripItem};
+    use rand::Rng;
+    use std::fs;
+
+    const FRAMES: &[&str] = &[
+        "data:image/jpeg;base64,/9j/4AAQSkZJRgABAQ==",
+        "data:image/jpeg;base64,/9j/4AAQSkZJRgABAg==",
+        "/9j/4AAQSkZJRgABAQ==",
+    ];
+
+    fn detail() -> AuditDetail {
+        let mut filmstrip = Filmstrip::default();
+        filmstrip.set_items(
+            FRAMES
+                .iter()
+                .map(|data| {
+                    let mut item = FilmstripItem::default();
+                    item.set_data((*data).to_owned());
+                    item
+                })
+                .collect(),
+        );
+        let mut audit = Audit::default();
+        audit.set_details(filmstrip);
+
+        let mut detail = AuditDetail::default();
+        detail.set_screenshot_thumbnails(Some(audit));
+        detail
+    }
+
+    fn frames(detail: &AuditDetail) -> Vec<&FilmstripItem> {
+        detail
+            .screenshot_thumbnails()
+            .as_ref()
+            .unwrap()
+            .details()
+            .items()
+            .iter()
+            .collect()
+    }
+
+    fn temp_store() -> FileSystemBlobStore {
+        FileSystemBlobStore::new(std::env::temp_dir().join(format!(
+            "slick-blobs-{:016x}",
+            rand::thread_rng().gen::<u64>()
+        )))
+    }
+
+    fn assert_round_trip<S: BlobStore>(store: &S) {
+        let mut detail = detail();
+
+        assert_eq!(externalise_filmstrip(&mut detail, store).unwrap(), 3);
+        assert!(frames(&detail).iter().all(|item| item.data().is_empty()));
+        // The first and last frames hold the same bytes and share a blob.
+        assert_eq!(referenced_blobs(vec![&detail]).len(), 2);
+
+        assert_eq!(hydrate_filmstrip(&mut detail, store).unwrap(), 3);
+        let data: Vec<&str> = frames(&detail)
+            .iter()
+            .map(|item| item.data().as_str())
+            .collect();
+        assert_eq!(data, FRAMES);
+        assert!(frames(&detail).iter().all(|item| item.blob().is_none()));
+    }
+
+    fn assert_missing_blob<S: BlobStore>(store: &S) {
+        let mut detail = detail();
+        externalise_filmstrip(&mut detail, store).unwrap();
+        let hash = frames(&detail)[1].blob().as_ref().unwrap().hash().clone();
+        assert!(store.delete(&hash).unwrap());
+
+        match hydrate_filmstrip(&mut detail, store) {
+            Err(BlobError::Missing(missing)) => assert_eq!(missing, hash),
+            other => panic!("expected a missing blob error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn memory_store_round_trip() {
+        assert_round_trip(&InMemoryBlobStore::new());
+    }
+
+    #[test]
+    fn memory_store_missing_blob() {
+        assert_missing_blob(&InMemoryBlobStore::new());
+    }
+
+    #[test]
+    fn filesystem_store_round_trip() {
+        let store = temp_store();
+        assert_round_trip(&store);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn filesystem_store_missing_blob() {
+        let store = temp_store();
+        assert_missing_blob(&store);
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+
+    #[test]
+    fn hydrate_rejects_content_with_another_hash() {
+        let store = temp_store();
+        let mut detail = detail();
+        externalise_filmstrip(&mut detail, &store).unwrap();
+        let hash = frames(&detail)[1].blob().as_ref().unwrap().hash().clone();
+        fs::write(store.root().join(&hash[..2]).join(&hash), b"corrupt").unwrap();
+
+        match hydrate_filmstrip(&mut detail, &store) {
+            Err(BlobError::HashMismatch { expected, actual }) => {
+                assert_eq!(expected, hash);
+                assert_eq!(actual, content_hash(b"corrupt"));
+            }
+            other => panic!("expected a hash mismatch, got {:?}", other),
+        }
+        fs::remove_dir_all(store.root()).unwrap();
+    }
+}
@@ -0,0 +1,107 @@
+use super::{content_hash, BlobError, BlobStore};
+use rand::Rng;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Stores each blob as a file named by its hash, fanned out into
+/// subdirectories by the first two hex digits.
+#[derive(Debug, Clone)]
+pub struct FileSystemBlobStore {
+    root: PathBuf,
+}
+
+impl FileSystemBlobStore {
+    pub fn new<P: AsRef<Path>>(root: P) -> FileSystemBlobStore {
+        FileSystemBlobStore {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn path(&self, hash: &str) -> Result<PathBuf, BlobError> {
+        if hash.len() < 3 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(BlobError::InvalidData(format!("invalid hash {}", hash)));
+        }
+
+        Ok(self.root.join(&hash[..2]).join(hash))
+    }
+}
+
+impl BlobStore for FileSystemBlobStore {
+    fn put(&self, data: &[u8]) -> Result<String, BlobError> {
+        let hash = content_hash(data);
+        let path = self.path(&hash)?;
+        if path.exists() {
+            return Ok(hash);
+        }
+
+        let directory = path.parent().expect("blob paths have a parent");
+        fs::create_dir_all(directory)?;
+        // Concurrent writers of the same content each use their own file and
+        // the last rename wins with identical bytes.
+        let suffix: u64 = rand::thread_rng().gen();
+        let temp_path = directory.join(format!("{}.{:016x}.tmp", hash, suffix));
+        fs::write(&temp_path, data)?;
+        fs::rename(&temp_path, &path)?;
+
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, BlobError> {
+        match fs::read(self.path(hash)?) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete(&self, hash: &str) -> Result<bool, BlobError> {
+        match fs::remove_file(self.path(hash)?) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn contains(&self, hash: &str) -> Result<bool, BlobError> {
+        Ok(self.path(hash)?.exists())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn concurrent_puts_of_the_same_content_succeed() {
+        let root = std::env::temp_dir().join(format!(
+            "slick-blobs-{:016x}",
+            rand::thread_rng().gen::<u64>()
+        ));
+        let store = Arc::new(FileSystemBlobStore::new(&root));
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let store = Arc::clone(&store);
+                thread::spawn(move || store.put(b"frame").unwrap())
+            })
+            .collect();
+        let hashes: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        assert!(hashes.iter().all(|hash| hash == &content_hash(b"frame")));
+        assert_eq!(store.get(&hashes[0]).unwrap(), Some(b"frame".to_vec()));
+        let leftovers = fs::read_dir(root.join(&hashes[0][..2]))
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("tmp".as_ref()))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,59 @@
+use super::{
+    from_gridfs, to_gridfs, BlobError, BlobStore, GridFsChunk, GridFsFile, DEFAULT_CHUNK_SIZE,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Holds blobs in the GridFS file/chunk layout, so the documents can be
+/// inspected or copied into `fs.files` and `fs.chunks`.
+#[derive(Debug, Default)]
+pub struct InMemoryBlobStore {
+    files: Mutex<HashMap<String, (GridFsFile, Vec<GridFsChunk>)>>,
+}
+
+impl InMemoryBlobStore {
+    pub fn new() -> InMemoryBlobStore {
+        InMemoryBlobStore::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn gridfs_documents(&self) -> Vec<(GridFsFile, Vec<GridFsChunk>)> {
+        self.files.lock().unwrap().values().cloned().collect()
+    }
+}
+
+impl BlobStore for InMemoryBlobStore {
+    fn put(&self, data: &[u8]) -> Result<String, BlobError> {
+        let (file, chunks) = to_gridfs(data, DEFAULT_CHUNK_SIZE);
+        let hash = file.id().clone();
+        self.files
+            .lock()
+            .unwrap()
+            .entry(hash.clone())
+            .or_insert((file, chunks));
+
+        Ok(hash)
+    }
+
+    fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, BlobError> {
+        match self.files.lock().unwrap().get(hash) {
+            Some((file, chunks)) => Ok(Some(from_gridfs(file, chunks)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn delete(&self, hash: &str) -> Result<bool, BlobError> {
+        Ok(self.files.lock().unwrap().remove(hash).is_some())
+    }
+
+    fn contains(&self, hash: &str) -> Result<bool, BlobError> {
+        Ok(self.files.lock().unwrap().contains_key(hash))
+    }
+}
@@ -0,0 +1,497 @@
+use crate::lh_models::NetworkRequest;
+use crate::metrics::Metric;
+use crate::{AuditDetail, AuditProfile, Site};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// One entry of a Lighthouse `budget.json`. Sizes are in KiB and timings in
+/// milliseconds, except the unitless cumulative layout shift.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct Budget {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<BudgetOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<Vec<TimingBudget>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resource_sizes: Option<Vec<ResourceBudget>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resource_counts: Option<Vec<ResourceBudget>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetOptions {
+    /// Hostnames, optionally starting with `*.`, counted as first party
+    /// instead of the page's own host.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    first_party_hostnames: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+pub struct TimingBudget {
+    /// Lighthouse audit id, e.g. `interactive`.
+    metric: String,
+    budget: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceBudget {
+    resource_type: ResourceType,
+    budget: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResourceType {
+    Total,
+    Document,
+    Script,
+    Stylesheet,
+    Image,
+    Media,
+    Font,
+    Other,
+    ThirdParty,
+}
+
+impl ResourceType {
+    pub fn id(&self) -> &'static str {
+        match self {
+            ResourceType::Total => "total",
+            ResourceType::Document => "document",
+            ResourceType::Script => "script",
+            ResourceType::Stylesheet => "stylesheet",
+            ResourceType::Image => "image",
+            ResourceType::Media => "media",
+            ResourceType::Font => "font",
+            ResourceType::Other => "other",
+            ResourceType::ThirdParty => "third-party",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<ResourceType> {
+        [
+            ResourceType::Total,
+            ResourceType::Document,
+            ResourceType::Script,
+            ResourceType::Stylesheet,
+            ResourceType::Image,
+            ResourceType::Media,
+            ResourceType::Font,
+            ResourceType::Other,
+            ResourceType::ThirdParty,
+        ]
+        .iter()
+        .copied()
+        .find(|t| t.id() == id)
+    }
+
+    /// Maps a Chrome network resource type, e.g. `Stylesheet` or `XHR`.
+    fn from_network_type(resource_type: Option<&str>) -> ResourceType {
+        match resource_type {
+            Some("Document") => ResourceType::Document,
+            Some("Script") => ResourceType::Script,
+            Some("Stylesheet") => ResourceType::Stylesheet,
+            Some("Image") => ResourceType::Image,
+            Some("Media") => ResourceType::Media,
+            Some("Font") => ResourceType::Font,
+            _ => ResourceType::Other,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ViolationKind {
+    ResourceSize,
+    ResourceCount,
+    Timing,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetViolation {
+    kind: ViolationKind,
+    /// Resource type or metric id.
+    key: String,
+    /// In the budget's units: KiB, requests or milliseconds.
+    budget: f64,
+    actual: f64,
+    overage: f64,
+}
+
+/// Budgets of an audit profile replace those of its site.
+pub fn budgets_for<'a>(site: &'a Site, audit_profile: &'a AuditProfile) -> Option<&'a [Budget]> {
+    audit_profile
+        .budgets()
+        .as_deref()
+        .or_else(|| site.budgets().as_deref())
+}
+
+/// The budget for a URL; as in Lighthouse, the last matching budget wins and
+/// a budget without a path matches every URL.
+pub fn matching_budget<'a>(budgets: &'a [Budget], url: &str) -> Option<&'a Budget> {
+    let path = url_path(url);
+    budgets
+        .iter()
+        .rev()
+        .find(|budget| budget.path.as_deref().is_none_or(|p| path_matches(p, path)))
+}
+
+/// Checks the budget matching the detail's final URL.
+pub fn evaluate_budgets(budgets: &[Budget], detail: &AuditDetail) -> Vec<BudgetViolation> {
+    matching_budget(budgets, detail.final_url())
+        .map(|budget| evaluate(budget, detail))
+        .unwrap_or_default()
+}
+
+pub fn evaluate(budget: &Budget, detail: &AuditDetail) -> Vec<BudgetViolation> {
+    let resources = resource_totals(budget, detail);
+    let mut violations = Vec::new();
+
+    for resource_budget in budget.resource_sizes.iter().flatten() {
+        let (_, transfer_size) = resources
+            .get(&resource_budget.resource_type)
+            .copied()
+            .unwrap_or_default();
+        push_violation(
+            &mut violations,
+            ViolationKind::ResourceSize,
+            resource_budget.resource_type.id(),
+            resource_budget.budget,
+            transfer_size as f64 / 1024.0,
+        );
+    }
+    for resource_budget in budget.resource_counts.iter().flatten() {
+        let (request_count, _) = resources
+            .get(&resource_budget.resource_type)
+            .copied()
+            .unwrap_or_default();
+        push_violation(
+            &mut violations,
+            ViolationKind::ResourceCount,
+            resource_budget.resource_type.id(),
+            resource_budget.budget,
+            request_count as f64,
+        );
+    }
+    for timing in budget.timings.iter().flatten() {
+        let value = Metric::from_id(&timing.metric)
+            .and_then(|metric| metric.web_vital_value(detail.web_vitals()));
+        if let Some(value) = value {
+            push_violation(
+                &mut violations,
+                ViolationKind::Timing,
+                &timing.metric,
+                timing.budget,
+                value,
+            );
+        }
+    }
+
+    violations
+}
+
+fn push_violation(
+    violations: &mut Vec<BudgetViolation>,
+    kind: ViolationKind,
+    key: &str,
+    budget: f64,
+    actual: f64,
+) {
+    if actual > budget {
+        violations.push(BudgetViolation {
+            kind,
+            key: key.to_owned(),
+            budget,
+            actual,
+            overage: actual - budget,
+        });
+    }
+}
+
+/// Request count and transfer size per resource type. Lighthouse's resource
+/// summary is used when present, except for third-party totals under custom
+/// first-party hostnames, which come from the network requests.
+fn resource_totals(budget: &Budget, detail: &AuditDetail) -> BTreeMap<ResourceType, (i64, i64)> {
+    let requests: &[NetworkRequest] = detail
+        .network_requests()
+        .as_ref()
+        .map(|audit| audit.details().items().as_slice())
+        .unwrap_or_default();
+    let first_party_hostnames = budget
+        .options
+        .as_ref()
+        .and_then(|options| options.first_party_hostnames.as_deref());
+
+    let mut totals = BTreeMap::new();
+    match detail.resource_summary() {
+        Some(summary) => {
+            for resource in summary.details().items() {
+                if let Some(resource_type) = ResourceType::from_id(resource.resource_type()) {
+                    totals.insert(
+                        resource_type,
+                        (
+                            *resource.request_count() as i64,
+                            resource.transfer_size().unwrap_or(0),
+                        ),
+                    );
+                }
+            }
+        }
+        None => {
+            for request in requests {
+                let transfer_size = request.transfer_size().unwrap_or(0);
+                for resource_type in [
+                    ResourceType::Total,
+                    ResourceType::from_network_type(request.resource_type().as_deref()),
+                ]
+                .iter()
+                {
+                    let total = totals.entry(*resource_type).or_insert((0, 0));
+                    total.0 += 1;
+                    total.1 += transfer_size;
+                }
+            }
+        }
+    }
+
+    if detail.resource_summary().is_none() || first_party_hostnames.is_some() {
+        let page_host = url_host(detail.final_url()).to_owned();
+        let third_party = requests
+            .iter()
+            .filter(|request| {
+                let host = url_host(request.url());
+                match first_party_hostnames {
+                    Some(hostnames) => !hostnames.iter().any(|h| hostname_matches(h, host)),
+                    None => host != page_host,
+                }
+            })
+            .fold((0, 0), |(count, size), request| {
+                (count + 1, size + request.transfer_size().unwrap_or(0))
+            });
+        totals.insert(ResourceType::ThirdParty, third_party);
+    }
+
+    totals
+}
+
+/// Lighthouse path patterns: `*` matches anything and a trailing `$` anchors
+/// the end; otherwise the pattern is a prefix.
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+    let parts: Vec<&str> = pattern.split('*').collect();
+
+    let mut rest = match path.strip_prefix(parts[0]) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    for (i, part) in parts.iter().enumerate().skip(1) {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// `*.example.com` matches `example.com` and its subdomains.
+fn hostname_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host == domain || host.ends_with(&format!(".{}", domain)),
+        None => host == pattern,
+    }
+}
+
+fn url_host(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = without_scheme.split(['/', '?', '#']).next().unwrap_or("");
+    let host = authority.rsplit('@').next().unwrap_or(authority);
+    host.split(':').next().unwrap_or(host)
+}
+
+/// Path and query of a URL, `/` when it has neither.
+fn url_path(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = without_scheme
+        .find(['/', '?'])
+        .map_or("/", |index| &without_scheme[index..]);
+    path.split('#').next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn budget(value: Value) -> Budget {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn audit<T: serde::de::DeserializeOwned>(id: &str, items: Value) -> Option<T> {
+        Some(
+            serde_json::from_value(json!({
+                "id": id,
+                "title": id,
+                "description": "",
+                "details": { "items": items },
+            }))
+            .unwrap(),
+        )
+    }
+
+    /// A detail for `https://www.example.com/` with 40 scripts of 102400
+    /// bytes (100 KiB) in total and two requests, one to a CDN.
+    fn detail(interactive: f64) -> AuditDetail {
+        let mut detail = AuditDetail::default();
+        detail.set_final_url("https://www.example.com/".to_owned());
+        detail.set_resource_summary(audit(
+            "resource-summary",
+            json!([
+                { "resourceType": "total", "label": "Total", "requestCount": 41, "transferSize": 112640 },
+                { "resourceType": "script", "label": "Script", "requestCount": 40, "transferSize": 102400 },
+            ]),
+        ));
+        detail.set_network_requests(audit(
+            "network-requests",
+            json!([
+                { "url": "https://www.example.com/", "transferSize": 10240, "resourceSize": 0, "statusCode": 200, "mimeType": "text/html" },
+                { "url": "https://cdn.example.com/app.js", "transferSize": 102400, "resourceSize": 0, "statusCode": 200, "mimeType": "text/javascript" },
+            ]),
+        ));
+        let mut web_vitals = detail.web_vitals().clone();
+        let mut audit = web_vitals.interactive().clone();
+        audit.set_numeric_value(Some(interactive));
+        web_vitals.set_interactive(audit);
+        detail.set_web_vitals(web_vitals);
+        detail
+    }
+
+    fn keys(violations: &[BudgetViolation]) -> Vec<&str> {
+        violations.iter().map(|v| v.key().as_str()).collect()
+    }
+
+    #[test]
+    fn budgets_pass_exactly_at_the_limit() {
+        let budget = budget(json!({
+            "resourceSizes": [{ "resourceType": "script", "budget": 100 }],
+            "resourceCounts": [{ "resourceType": "script", "budget": 40 }],
+            "timings": [{ "metric": "interactive", "budget": 4000 }],
+        }));
+
+        assert!(evaluate(&budget, &detail(4000.0)).is_empty());
+    }
+
+    #[test]
+    fn budgets_fail_just_over_the_limit() {
+        let budget = budget(json!({
+            "resourceSizes": [{ "resourceType": "script", "budget": 99.999 }],
+            "resourceCounts": [{ "resourceType": "script", "budget": 39 }],
+            "timings": [{ "metric": "interactive", "budget": 4000 }],
+        }));
+
+        let violations = evaluate(&budget, &detail(4000.5));
+
+        assert_eq!(
+            violations
+                .iter()
+                .map(|v| (*v.kind(), v.key().as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (ViolationKind::ResourceSize, "script"),
+                (ViolationKind::ResourceCount, "script"),
+                (ViolationKind::Timing, "interactive"),
+            ]
+        );
+        assert!((violations[0].overage() - 0.001).abs() < 1e-9);
+        assert_eq!(*violations[1].overage(), 1.0);
+        assert_eq!(*violations[2].overage(), 0.5);
+    }
+
+    #[test]
+    fn first_party_hostnames_decide_third_party_totals() {
+        let third_party_budget = |options: Value| {
+            budget(json!({
+                "options": options,
+                "resourceCounts": [{ "resourceType": "third-party", "budget": 0 }],
+            }))
+        };
+
+        let own_host = third_party_budget(json!({ "firstPartyHostnames": ["www.example.com"] }));
+        let whole_domain = third_party_budget(json!({ "firstPartyHostnames": ["*.example.com"] }));
+
+        assert_eq!(
+            keys(&evaluate(&own_host, &detail(0.0))),
+            vec!["third-party"]
+        );
+        assert!(evaluate(&whole_domain, &detail(0.0)).is_empty());
+    }
+
+    #[test]
+    fn last_matching_budget_wins() {
+        let budgets = vec![
+            budget(json!({ "timings": [{ "metric": "interactive", "budget": 1 }] })),
+            budget(json!({ "path": "/checkout*$", "timings": [] })),
+            budget(json!({ "path": "/blog", "timings": [] })),
+        ];
+        let path = |url| matching_budget(&budgets, url).and_then(|b| b.path().clone());
+
+        assert_eq!(
+            path("https://example.com/blog/post?page=2"),
+            Some("/blog".to_owned())
+        );
+        assert_eq!(
+            path("https://example.com/checkout/cart"),
+            Some("/checkout*$".to_owned())
+        );
+        assert_eq!(path("https://example.com/"), None);
+        assert_eq!(
+            keys(&evaluate_budgets(&budgets, &detail(4000.0))),
+            vec!["interactive"]
+        );
+    }
+
+    #[test]
+    fn path_patterns() {
+        assert!(path_matches("/", "/anything"));
+        assert!(path_matches("/blog", "/blog/post"));
+        assert!(!path_matches("/blog$", "/blog/post"));
+        assert!(path_matches("/*.html$", "/docs/index.html"));
+        assert!(!path_matches("/*.html$", "/docs/index.html?x=1"));
+        assert!(path_matches("/*/edit", "/posts/1/edit/preview"));
+        assert_eq!(url_path("https://example.com"), "/");
+        assert_eq!(
+            url_host("https://user@example.com:8080/path"),
+            "example.com"
+        );
+    }
+
+    #[test]
+    fn profile_budgets_replace_site_budgets() {
+        let mut site = Site::default();
+        site.set_budgets(Some(vec![Budget::default()]));
+        let mut audit_profile = AuditProfile::default();
+
+        assert_eq!(budgets_for(&site, &audit_profile).map(|b| b.len()), Some(1));
+        audit_profile.set_budgets(Some(Vec::new()));
+        assert_eq!(budgets_for(&site, &audit_profile).map(|b| b.len()), Some(0));
+    }
+}
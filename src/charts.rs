@@ -0,0 +1,527 @@
+use crate::format::escape_html;
+use crate::metrics::{Metric, Rating};
+use crate::AuditSummary;
+use chrono::{DateTime, TimeZone, Utc};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const SERIES_COLORS: [&str; 6] = [
+    "#1a73e8", "#e8710a", "#188038", "#9334e6", "#d01884", "#12b5cb",
+];
+const TEXT_STYLE: &str = "font-family=\"sans-serif\" font-size=\"10\" fill=\"#5f6368\"";
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, Copy, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct ChartOptions {
+    width: f64,
+    /// Height of the plot, or of each panel in a trend chart.
+    height: f64,
+    /// Shade the good, needs-improvement and poor ranges of the metric.
+    bands: bool,
+}
+
+impl Default for ChartOptions {
+    fn default() -> ChartOptions {
+        ChartOptions {
+            width: 480.0,
+            height: 160.0,
+            bands: true,
+        }
+    }
+}
+
+impl ChartOptions {
+    pub fn new(width: f64, height: f64, bands: bool) -> ChartOptions {
+        ChartOptions {
+            width,
+            height,
+            bands,
+        }
+    }
+}
+
+/// One line: the runs of a page and audit profile, oldest first.
+struct Series {
+    label: String,
+    points: Vec<(i64, f64)>,
+}
+
+/// Maps fetch times and values to the plot area.
+struct Scale {
+    times: (i64, i64),
+    values: (f64, f64),
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+}
+
+impl Scale {
+    fn x(&self, time: i64) -> f64 {
+        let (start, end) = self.times;
+        if end == start {
+            return self.left + self.width / 2.0;
+        }
+        self.left + (time - start) as f64 / (end - start) as f64 * self.width
+    }
+
+    fn y(&self, value: f64) -> f64 {
+        let (low, high) = self.values;
+        let ratio = if high > low {
+            (value - low) / (high - low)
+        } else {
+            0.5
+        };
+        self.top + (1.0 - ratio.clamp(0.0, 1.0)) * self.height
+    }
+}
+
+/// A small line of one metric across runs, without axes; the last run is
+/// marked in its rating's colour. Summaries of different pages or audit
+/// profiles are drawn as one line, so filter them first. `None` when no run
+/// has the metric.
+pub fn sparkline(
+    summaries: &[AuditSummary],
+    metric: Metric,
+    options: &ChartOptions,
+) -> Option<String> {
+    let mut points: Vec<(i64, f64)> = summaries
+        .iter()
+        .filter_map(|summary| point(summary, metric))
+        .collect();
+    points.sort_by_key(|(time, _)| *time);
+    let &(last_time, last_value) = points.last()?;
+
+    let scale = scale(
+        metric,
+        &points,
+        2.0,
+        2.0,
+        options.width - 4.0,
+        options.height - 4.0,
+    );
+    let mut svg = svg_open(options.width, options.height, metric.label());
+    if options.bands {
+        svg.push_str(&bands(metric, &scale));
+    }
+    svg.push_str(&line(&points, &scale, SERIES_COLORS[0], 1.5));
+    let _ = write!(
+        svg,
+        "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\" fill=\"{}\"/>",
+        scale.x(last_time),
+        scale.y(last_value),
+        metric.rating(last_value).color()
+    );
+    svg.push_str("</svg>");
+    Some(svg)
+}
+
+/// A titled line chart of one metric with a line per page and audit
+/// profile, value labels on the left and the date range below. `None` when
+/// no run has the metric.
+pub fn line_chart(
+    summaries: &[AuditSummary],
+    metric: Metric,
+    options: &ChartOptions,
+) -> Option<String> {
+    let series = series(summaries, metric);
+    if series.is_empty() {
+        return None;
+    }
+
+    let height = panel_height(&series, options);
+    let mut svg = svg_open(options.width, height, metric.label());
+    svg.push_str(&panel(&series, metric, options));
+    svg.push_str("</svg>");
+    Some(svg)
+}
+
+/// The score and the web vitals as stacked line charts in one image;
+/// metrics no run has are left out.
+pub fn trend_chart(summaries: &[AuditSummary], options: &ChartOptions) -> String {
+    let mut panels = String::new();
+    let mut height = 0.0;
+    for metric in std::iter::once(Metric::Score).chain(Metric::web_vitals().iter().copied()) {
+        let series = series(summaries, metric);
+        if series.is_empty() {
+            continue;
+        }
+        let _ = write!(
+            panels,
+            "<g transform=\"translate(0,{:.1})\">{}</g>",
+            height,
+            panel(&series, metric, options)
+        );
+        height += panel_height(&series, options);
+    }
+
+    let mut svg = svg_open(options.width, height, "Performance trends");
+    svg.push_str(&panels);
+    svg.push_str("</svg>");
+    svg
+}
+
+const TITLE_HEIGHT: f64 = 18.0;
+const AXIS_HEIGHT: f64 = 16.0;
+const LEGEND_HEIGHT: f64 = 14.0;
+const AXIS_WIDTH: f64 = 48.0;
+
+fn panel_height(series: &[Series], options: &ChartOptions) -> f64 {
+    let legend = if series.len() > 1 { LEGEND_HEIGHT } else { 0.0 };
+    TITLE_HEIGHT + options.height + AXIS_HEIGHT + legend
+}
+
+fn panel(series: &[Series], metric: Metric, options: &ChartOptions) -> String {
+    let points: Vec<(i64, f64)> = series.iter().flat_map(|s| s.points.clone()).collect();
+    let scale = scale(
+        metric,
+        &points,
+        AXIS_WIDTH,
+        TITLE_HEIGHT,
+        options.width - AXIS_WIDTH - 8.0,
+        options.height,
+    );
+
+    let mut svg = String::new();
+    let _ = write!(
+        svg,
+        "<text x=\"{:.1}\" y=\"12\" font-family=\"sans-serif\" font-size=\"12\" font-weight=\"bold\" fill=\"#202124\">{}</text>",
+        AXIS_WIDTH,
+        escape_html(metric.label())
+    );
+    if options.bands {
+        svg.push_str(&bands(metric, &scale));
+    }
+    let _ = write!(
+        svg,
+        "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"none\" stroke=\"#dadce0\"/>",
+        scale.left, scale.top, scale.width, scale.height
+    );
+
+    let (low, high) = scale.values;
+    for (value, baseline) in [(high, "hanging"), (low, "auto")].iter() {
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" dominant-baseline=\"{}\" {}>{}</text>",
+            AXIS_WIDTH - 4.0,
+            scale.y(*value),
+            baseline,
+            TEXT_STYLE,
+            escape_html(&metric.format(*value))
+        );
+    }
+    let axis_y = scale.top + scale.height + 12.0;
+    let (start, end) = scale.times;
+    let _ = write!(
+        svg,
+        "<text x=\"{:.1}\" y=\"{:.1}\" {}>{}</text>",
+        scale.left,
+        axis_y,
+        TEXT_STYLE,
+        date_label(start)
+    );
+    if end != start {
+        let _ = write!(
+            svg,
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\" {}>{}</text>",
+            scale.left + scale.width,
+            axis_y,
+            TEXT_STYLE,
+            date_label(end)
+        );
+    }
+
+    for (i, s) in series.iter().enumerate() {
+        let color = SERIES_COLORS[i % SERIES_COLORS.len()];
+        svg.push_str(&line(&s.points, &scale, color, 1.5));
+        if let Some(&(time, value)) = s.points.last() {
+            let _ = write!(
+                svg,
+                "<circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"2.5\" fill=\"{}\"/>",
+                scale.x(time),
+                scale.y(value),
+                color
+            );
+        }
+    }
+
+    if series.len() > 1 {
+        let legend_y = axis_y + LEGEND_HEIGHT;
+        let entry_width = scale.width / series.len() as f64;
+        for (i, s) in series.iter().enumerate() {
+            let x = scale.left + i as f64 * entry_width;
+            let _ = write!(
+                svg,
+                "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"8\" height=\"8\" fill=\"{}\"/><text x=\"{:.1}\" y=\"{:.1}\" {}>{}</text>",
+                x,
+                legend_y - 8.0,
+                SERIES_COLORS[i % SERIES_COLORS.len()],
+                x + 11.0,
+                legend_y,
+                TEXT_STYLE,
+                escape_html(&s.label)
+            );
+        }
+    }
+
+    svg
+}
+
+fn svg_open(width: f64, height: f64, title: &str) -> String {
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w:.0}\" height=\"{h:.0}\" viewBox=\"0 0 {w:.0} {h:.0}\" role=\"img\" aria-label=\"{t}\"><title>{t}</title>",
+        w = width,
+        h = height,
+        t = escape_html(title)
+    )
+}
+
+fn line(points: &[(i64, f64)], scale: &Scale, color: &str, stroke_width: f64) -> String {
+    if points.len() < 2 {
+        return String::new();
+    }
+    let coordinates: Vec<String> = points
+        .iter()
+        .map(|(time, value)| format!("{:.1},{:.1}", scale.x(*time), scale.y(*value)))
+        .collect();
+    format!(
+        "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linejoin=\"round\" stroke-linecap=\"round\"/>",
+        coordinates.join(" "),
+        color,
+        stroke_width
+    )
+}
+
+/// Shaded ranges of the metric's ratings within the scale.
+fn bands(metric: Metric, scale: &Scale) -> String {
+    let (good, poor) = metric.thresholds();
+    let (low, high) = scale.values;
+    let ranges = if metric.higher_is_better() {
+        [
+            (low, poor, Rating::Poor),
+            (poor, good, Rating::NeedsImprovement),
+            (good, high, Rating::Good),
+        ]
+    } else {
+        [
+            (low, good, Rating::Good),
+            (good, poor, Rating::NeedsImprovement),
+            (poor, high, Rating::Poor),
+        ]
+    };
+
+    let mut svg = String::new();
+    for (from, to, rating) in ranges.iter() {
+        let from = from.max(low);
+        let to = to.min(high);
+        if to <= from {
+            continue;
+        }
+        let top = scale.y(to);
+        let _ = write!(
+            svg,
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\" fill-opacity=\"0.12\"/>",
+            scale.left,
+            top,
+            scale.width,
+            scale.y(from) - top,
+            rating.color()
+        );
+    }
+    svg
+}
+
+/// Scores span 0..=1; other metrics start at 0 and leave room above the
+/// highest value and the poor threshold.
+fn scale(
+    metric: Metric,
+    points: &[(i64, f64)],
+    left: f64,
+    top: f64,
+    width: f64,
+    height: f64,
+) -> Scale {
+    let times = (
+        points.iter().map(|(time, _)| *time).min().unwrap_or(0),
+        points.iter().map(|(time, _)| *time).max().unwrap_or(0),
+    );
+    let values = if metric == Metric::Score {
+        (0.0, 1.0)
+    } else {
+        let (_, poor) = metric.thresholds();
+        let highest = points.iter().map(|(_, value)| *value).fold(poor, f64::max);
+        (0.0, highest * 1.1)
+    };
+
+    Scale {
+        times,
+        values,
+        left,
+        top,
+        width,
+        height,
+    }
+}
+
+fn series(summaries: &[AuditSummary], metric: Metric) -> Vec<Series> {
+    let mut grouped: BTreeMap<(&str, &str), Series> = BTreeMap::new();
+    for summary in summaries {
+        if let Some(point) = point(summary, metric) {
+            grouped
+                .entry((
+                    summary.page_id().as_str(),
+                    summary.audit_profile_id().as_str(),
+                ))
+                .or_insert_with(|| Series {
+                    label: format!("{} · {}", summary.page_id(), summary.audit_profile().name()),
+                    points: Vec::new(),
+                })
+                .points
+                .push(point);
+        }
+    }
+
+    grouped
+        .into_values()
+        .map(|mut series| {
+            series.points.sort_by_key(|(time, _)| *time);
+            series
+        })
+        .collect()
+}
+
+/// Fetch time in milliseconds and the metric's value.
+fn point(summary: &AuditSummary, metric: Metric) -> Option<(i64, f64)> {
+    let fetch_time = DateTime::parse_from_rfc3339(summary.fetch_time()).ok()?;
+    Some((fetch_time.timestamp_millis(), metric.value(summary)?))
+}
+
+fn date_label(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .map(|date| date.format("%Y-%m-%d").to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn summary() -> AuditSummary {
+        serde_json::from_str(include_str!("../tests/fixtures/audit_summary.json")).unwrap()
+    }
+
+    /// Two mobile runs of the fixture page a day apart and one desktop run.
+    fn runs() -> Vec<AuditSummary> {
+        let first = summary();
+
+        let mut second = first.clone();
+        second.set_fetch_time("2020-10-17T22:14:08.771Z".to_owned());
+        let mut categories = second.categories().clone();
+        let mut performance = categories.performance().clone();
+        performance.set_score(0.91);
+        categories.set_performance(performance);
+        second.set_categories(categories);
+        let mut web_vitals = second.web_vitals().clone();
+        let mut speed_index = web_vitals.speed_index().clone();
+        speed_index.set_numeric_value(Some(2480.0));
+        web_vitals.set_speed_index(speed_index);
+        second.set_web_vitals(web_vitals);
+
+        let mut desktop = first.clone();
+        desktop.set_audit_profile_id("desktop".to_owned());
+        let mut profile = desktop.audit_profile().clone();
+        profile.set_id("desktop".to_owned());
+        profile.set_name("Desktop".to_owned());
+        desktop.set_audit_profile(profile);
+        desktop.set_fetch_time("2020-10-17T10:00:00.000Z".to_owned());
+
+        vec![second, first, desktop]
+    }
+
+    fn mobile_runs() -> Vec<AuditSummary> {
+        runs()
+            .into_iter()
+            .filter(|summary| summary.audit_profile_id() != "desktop")
+            .collect()
+    }
+
+    #[test]
+    fn sparkline_matches_snapshot() {
+        let svg = sparkline(
+            &mobile_runs(),
+            Metric::SpeedIndex,
+            &ChartOptions::new(120.0, 24.0, true),
+        );
+
+        assert_eq!(
+            svg.as_deref(),
+            Some(include_str!("../tests/fixtures/charts/sparkline.svg").trim_end())
+        );
+    }
+
+    #[test]
+    fn line_chart_matches_snapshot() {
+        let svg = line_chart(&runs(), Metric::Score, &ChartOptions::default());
+
+        assert_eq!(
+            svg.as_deref(),
+            Some(include_str!("../tests/fixtures/charts/line_chart.svg").trim_end())
+        );
+    }
+
+    #[test]
+    fn trend_chart_matches_snapshot() {
+        let svg = trend_chart(&mobile_runs(), &ChartOptions::new(480.0, 80.0, false));
+
+        assert_eq!(
+            svg,
+            include_str!("../tests/fixtures/charts/trend_chart.svg").trim_end()
+        );
+    }
+
+    #[test]
+    fn charts_without_runs() {
+        let options = ChartOptions::default();
+
+        assert_eq!(sparkline(&[], Metric::Score, &options), None);
+        assert_eq!(line_chart(&[], Metric::Score, &options), None);
+        assert_eq!(
+            trend_chart(&[], &options),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"480\" height=\"0\" viewBox=\"0 0 480 0\" role=\"img\" aria-label=\"Performance trends\"><title>Performance trends</title></svg>"
+        );
+    }
+
+    #[test]
+    fn legend_labels_are_escaped() {
+        let svg = line_chart(&runs(), Metric::Score, &ChartOptions::default()).unwrap();
+
+        assert!(svg.contains(">home · Mobile &lt;Moto G4&gt; &amp; 4G</text>"));
+        assert!(!svg.contains("<Moto G4>"));
+    }
+
+    /// The `y` of the rating band drawn in `color`.
+    fn band_y(svg: &str, color: &str) -> f64 {
+        let end = svg
+            .find(&format!("fill=\"{}\" fill-opacity", color))
+            .unwrap();
+        let start = svg[..end].rfind(" y=\"").unwrap() + 4;
+        svg[start..].split('"').next().unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn good_band_is_at_the_better_end_of_the_axis() {
+        let options = ChartOptions::new(120.0, 24.0, true);
+
+        let speed_index = sparkline(&mobile_runs(), Metric::SpeedIndex, &options).unwrap();
+        assert!(
+            band_y(&speed_index, Rating::Good.color()) > band_y(&speed_index, Rating::Poor.color())
+        );
+
+        let score = sparkline(&mobile_runs(), Metric::Score, &options).unwrap();
+        assert!(band_y(&score, Rating::Good.color()) < band_y(&score, Rating::Poor.color()));
+    }
+}
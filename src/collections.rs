@@ -0,0 +1,158 @@
+use bson::{doc, Document};
+use getset::{Getters, Setters};
+
+pub const SITES: &str = "sites";
+pub const AUDIT_SUMMARIES: &str = "auditSummaries";
+pub const AUDIT_DETAILS: &str = "auditDetails";
+pub const AUDIT_DETAIL_CHUNKS: &str = "auditDetailChunks";
+pub const SITE_RUNS: &str = "siteRuns";
+pub const SLOS: &str = "slos";
+pub const ALERT_RULES: &str = "alertRules";
+pub const ALERT_EVENTS: &str = "alertEvents";
+
+#[derive(Debug, Getters, Setters, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+pub struct IndexDefinition {
+    collection: String,
+    name: String,
+    keys: Document,
+    unique: bool,
+}
+
+impl IndexDefinition {
+    pub fn new(collection: &str, name: &str, keys: Document) -> IndexDefinition {
+        IndexDefinition {
+            collection: collection.to_owned(),
+            name: name.to_owned(),
+            keys,
+            unique: false,
+        }
+    }
+
+    pub fn new_unique(collection: &str, name: &str, keys: Document) -> IndexDefinition {
+        IndexDefinition {
+            unique: true,
+            ..IndexDefinition::new(collection, name, keys)
+        }
+    }
+
+    /// The index specification as listed in a `createIndexes` command.
+    pub fn to_document(&self) -> Document {
+        let mut index = doc! {
+            "key": self.keys.clone(),
+            "name": self.name.clone(),
+        };
+        if self.unique {
+            index.insert("unique", true);
+        }
+        index
+    }
+}
+
+pub fn indexes() -> Vec<IndexDefinition> {
+    vec![
+        IndexDefinition::new(SITES, "groupId", doc! { "groupId": 1 }),
+        IndexDefinition::new(
+            AUDIT_SUMMARIES,
+            "siteId_pageId_auditProfileId_fetchTime",
+            doc! { "siteId": 1, "pageId": 1, "auditProfileId": 1, "fetchTime": -1 },
+        ),
+        IndexDefinition::new(
+            AUDIT_SUMMARIES,
+            "siteId_siteRunId",
+            doc! { "siteId": 1, "siteRunId": 1 },
+        ),
+        IndexDefinition::new(
+            AUDIT_DETAILS,
+            "siteId_pageId_auditProfileId_fetchTime",
+            doc! { "siteId": 1, "pageId": 1, "auditProfileId": 1, "fetchTime": -1 },
+        ),
+        IndexDefinition::new(
+            AUDIT_DETAIL_CHUNKS,
+            "auditDetailId_table_index",
+            doc! { "auditDetailId": 1, "table": 1, "index": 1 },
+        ),
+        IndexDefinition::new_unique(SITE_RUNS, "siteId_runId", doc! { "siteId": 1, "runId": -1 }),
+        IndexDefinition::new(
+            SLOS,
+            "siteId_pageId_auditProfileId",
+            doc! { "siteId": 1, "pageId": 1, "auditProfileId": 1 },
+        ),
+        IndexDefinition::new(
+            ALERT_RULES,
+            "siteId_pageId_auditProfileId",
+            doc! { "siteId": 1, "pageId": 1, "auditProfileId": 1 },
+        ),
+        IndexDefinition::new(
+            ALERT_EVENTS,
+            "dedupKey_triggeredAt",
+            doc! { "dedupKey": 1, "triggeredAt": -1 },
+        ),
+    ]
+}
+
+pub fn indexes_for(collection: &str) -> Vec<IndexDefinition> {
+    indexes()
+        .into_iter()
+        .filter(|index| index.collection == collection)
+        .collect()
+}
+
+/// One `createIndexes` command per collection with indexes, in the order
+/// the collections first appear in `indexes`, suitable for deployment
+/// scripts.
+pub fn create_indexes_commands() -> Vec<Document> {
+    let mut commands: Vec<(String, Vec<Document>)> = Vec::new();
+    for index in indexes() {
+        let document = index.to_document();
+        match commands
+            .iter_mut()
+            .find(|(collection, _)| *collection == index.collection)
+        {
+            Some((_, documents)) => documents.push(document),
+            None => commands.push((index.collection, vec![document])),
+        }
+    }
+
+    commands
+        .into_iter()
+        .map(|(collection, indexes)| doc! { "createIndexes": collection, "indexes": indexes })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_indexes_commands_cover_every_index_once() {
+        let commands = create_indexes_commands();
+
+        let collections: Vec<&str> = commands
+            .iter()
+            .map(|command| command.get_str("createIndexes").unwrap())
+            .collect();
+        assert_eq!(
+            collections,
+            vec![
+                SITES,
+                AUDIT_SUMMARIES,
+                AUDIT_DETAILS,
+                AUDIT_DETAIL_CHUNKS,
+                SITE_RUNS,
+                SLOS,
+                ALERT_RULES,
+                ALERT_EVENTS
+            ]
+        );
+        let total: usize = commands
+            .iter()
+            .map(|command| command.get_array("indexes").unwrap().len())
+            .sum();
+        assert_eq!(total, indexes().len());
+        assert_eq!(
+            commands[1].get_array("indexes").unwrap().len(),
+            indexes_for(AUDIT_SUMMARIES).len()
+        );
+    }
+}
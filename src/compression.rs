@@ -0,0 +1,390 @@
+use crate::fields::field;
+use crate::migrations::{self, Migration};
+use crate::repository::{Record, RecordFilter};
+use crate::AuditDetail;
+use bson::{doc, oid::ObjectId, spec::BinarySubtype, Binary, Bson, Document};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::io::{self, Read, Write};
+
+const COMPRESSION: &str = "compression";
+const DATA: &str = "data";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+    Gzip,
+}
+
+impl Codec {
+    fn name(&self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Gzip => "gzip",
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Codec, CompressionError> {
+        match name {
+            "zstd" => Ok(Codec::Zstd),
+            "gzip" => Ok(Codec::Gzip),
+            _ => Err(CompressionError::UnknownCodec(name.to_owned())),
+        }
+    }
+
+    fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zstd => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL),
+            Codec::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zstd => zstd::decode_all(bytes),
+            Codec::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(bytes).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompressionError {
+    Io(io::Error),
+    Serialization(bson::ser::Error),
+    Deserialization(bson::de::Error),
+    UnknownCodec(String),
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::Io(e) => write!(f, "compression failed: {}", e),
+            CompressionError::Serialization(e) => write!(f, "failed to serialize section: {}", e),
+            CompressionError::Deserialization(e) => {
+                write!(f, "failed to deserialize section: {}", e)
+            }
+            CompressionError::UnknownCodec(name) => write!(f, "unknown codec {}", name),
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {}
+
+impl From<io::Error> for CompressionError {
+    fn from(e: io::Error) -> CompressionError {
+        CompressionError::Io(e)
+    }
+}
+
+impl From<bson::ser::Error> for CompressionError {
+    fn from(e: bson::ser::Error) -> CompressionError {
+        CompressionError::Serialization(e)
+    }
+}
+
+impl From<bson::de::Error> for CompressionError {
+    fn from(e: bson::de::Error) -> CompressionError {
+        CompressionError::Deserialization(e)
+    }
+}
+
+/// The `AuditDetail` sections stored compressed: the large tables and the
+/// opportunities.
+pub fn compressed_sections() -> Vec<&'static str> {
+    [
+        "network_requests",
+        "main_thread_tasks",
+        "uses_responsive_images",
+        "uses_optimized_images",
+        "uses_webp_images",
+        "offscreen_images",
+        "uses_http2",
+        "unminified_css",
+        "unminified_javascript",
+        "unused_css_rules",
+        "unused_javascript",
+        "render_blocking_resources",
+    ]
+    .iter()
+    .map(|section| field::<AuditDetail>(section))
+    .collect()
+}
+
+/// Replaces a section value with `{ compression, data }`, where `data` holds
+/// the compressed BSON encoding of the value.
+pub fn compress_section(value: &Bson, codec: Codec) -> Result<Document, CompressionError> {
+    let mut bytes = Vec::new();
+    doc! { "v": value.clone() }.to_writer(&mut bytes)?;
+
+    Ok(doc! {
+        COMPRESSION: codec.name(),
+        DATA: Binary {
+            subtype: BinarySubtype::Generic,
+            bytes: codec.compress(&bytes)?,
+        },
+    })
+}
+
+/// Decodes a section written by `compress_section`; returns `None` for
+/// values that are not compressed.
+pub fn decompress_section(value: &Bson) -> Result<Option<(Codec, Bson)>, CompressionError> {
+    let section = match value {
+        Bson::Document(section) if section.len() == 2 => section,
+        _ => return Ok(None),
+    };
+    let (codec, bytes) = match (section.get(COMPRESSION), section.get(DATA)) {
+        (Some(Bson::String(codec)), Some(Bson::Binary(binary))) => {
+            (Codec::from_name(codec)?, &binary.bytes)
+        }
+        _ => return Ok(None),
+    };
+
+    let decompressed = codec.decompress(bytes)?;
+    let mut document = Document::from_reader(&mut decompressed.as_slice())?;
+    Ok(Some((codec, document.remove("v").unwrap_or(Bson::Null))))
+}
+
+pub fn compress_document(document: &mut Document, codec: Codec) -> Result<(), CompressionError> {
+    for section in compressed_sections() {
+        let compressed = match document.get(section) {
+            None | Some(Bson::Null) => continue,
+            Some(value) => compress_section(value, codec)?,
+        };
+        document.insert(section, compressed);
+    }
+
+    Ok(())
+}
+
+/// Decodes every compressed section in place, returning the codec found.
+pub fn decompress_document(document: &mut Document) -> Result<Option<Codec>, CompressionError> {
+    let mut found = None;
+    for section in compressed_sections() {
+        let decompressed = match document.get(section) {
+            Some(value) => decompress_section(value)?,
+            None => None,
+        };
+        if let Some((codec, value)) = decompressed {
+            document.insert(section, value);
+            found = found.or(Some(codec));
+        }
+    }
+
+    Ok(found)
+}
+
+/// Storage form of an `AuditDetail` whose payload sections are written
+/// compressed. Reading accepts both compressed and plain documents.
+#[derive(Debug, Clone)]
+pub struct CompressedAuditDetail {
+    detail: AuditDetail,
+    codec: Option<Codec>,
+}
+
+impl CompressedAuditDetail {
+    pub fn new(detail: AuditDetail, codec: Codec) -> CompressedAuditDetail {
+        CompressedAuditDetail {
+            detail,
+            codec: Some(codec),
+        }
+    }
+
+    pub fn detail(&self) -> &AuditDetail {
+        &self.detail
+    }
+
+    /// The codec used when writing; `None` writes the sections uncompressed.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
+    }
+
+    pub fn into_inner(self) -> AuditDetail {
+        self.detail
+    }
+}
+
+impl Serialize for CompressedAuditDetail {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut document = bson::to_document(&self.detail).map_err(ser::Error::custom)?;
+        if let Some(codec) = self.codec {
+            compress_document(&mut document, codec).map_err(ser::Error::custom)?;
+        }
+        document.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CompressedAuditDetail {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut document = Document::deserialize(deserializer)?;
+        let codec = decompress_document(&mut document).map_err(de::Error::custom)?;
+        let detail = bson::from_document(document).map_err(de::Error::custom)?;
+        Ok(CompressedAuditDetail { detail, codec })
+    }
+}
+
+/// Lets a repository store details compressed in the same collection.
+impl Record for CompressedAuditDetail {
+    const COLLECTION: &'static str = AuditDetail::COLLECTION;
+    const SCHEMA_VERSION: i32 = AuditDetail::SCHEMA_VERSION;
+
+    fn migrations() -> Vec<Migration> {
+        migrations::audit_detail_migrations()
+    }
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.detail.record_id()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.detail.assign_id(id)
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        self.detail.matches(filter)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        AuditDetail::filter_document(filter)
+    }
+
+    fn page_key(&self) -> Option<(&str, &str)> {
+        self.detail.page_key()
+    }
+
+    fn recency(&self) -> Option<&str> {
+        self.detail.recency()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lh_models::{AuditTable, NetworkRequest, Table};
+
+    const CODECS: &[Codec] = &[Codec::Gzip, Codec::Zstd];
+
+    fn section() -> Bson {
+        Bson::Array(
+            (0..50)
+                .map(|i| {
+                    Bson::Document(doc! { "url": format!("https://example.com/{}", i), "size": i })
+                })
+                .collect(),
+        )
+    }
+
+    fn detail() -> AuditDetail {
+        let mut request = NetworkRequest::default();
+        request.set_url("https://example.com/".to_owned());
+        request.set_resource_size(1024);
+        let mut table = Table::default();
+        table.set_items(vec![request; 20]);
+        let mut network_requests = AuditTable::default();
+        network_requests.set_details(table);
+
+        let mut detail = AuditDetail::default();
+        detail.set_network_requests(Some(network_requests));
+        detail
+    }
+
+    #[test]
+    fn section_round_trip() {
+        for codec in CODECS {
+            let compressed = compress_section(&section(), *codec).unwrap();
+
+            assert_eq!(compressed.get_str(COMPRESSION).unwrap(), codec.name());
+            assert_eq!(
+                decompress_section(&Bson::Document(compressed)).unwrap(),
+                Some((*codec, section()))
+            );
+        }
+    }
+
+    #[test]
+    fn plain_values_are_not_decompressed() {
+        assert_eq!(decompress_section(&section()).unwrap(), None);
+        assert_eq!(
+            decompress_section(&Bson::Document(doc! { "compression": "gzip", "data": 1 })).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn document_round_trip() {
+        let plain = bson::to_document(&detail()).unwrap();
+
+        for codec in CODECS {
+            let mut document = plain.clone();
+            compress_document(&mut document, *codec).unwrap();
+            assert_ne!(document, plain);
+            assert!(document.get_document("networkRequests").is_ok());
+
+            assert_eq!(decompress_document(&mut document).unwrap(), Some(*codec));
+            // Sections are reinserted at the end, so compare the detail read back.
+            let read: AuditDetail = bson::from_document(document).unwrap();
+            assert_eq!(bson::to_document(&read).unwrap(), plain);
+        }
+    }
+
+    #[test]
+    fn compressed_detail_round_trip() {
+        for codec in CODECS {
+            let written = bson::to_document(&CompressedAuditDetail::new(detail(), *codec)).unwrap();
+            let read: CompressedAuditDetail = bson::from_document(written).unwrap();
+
+            assert_eq!(read.codec(), Some(*codec));
+            assert_eq!(
+                bson::to_document(read.detail()).unwrap(),
+                bson::to_document(&detail()).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn corrupt_data_is_an_error() {
+        for codec in CODECS {
+            let mut compressed = compress_section(&section(), *codec).unwrap();
+            let mut bytes = match compressed.get(DATA) {
+                Some(Bson::Binary(binary)) => binary.bytes.clone(),
+                other => panic!("expected binary data, got {:?}", other),
+            };
+            bytes.truncate(bytes.len() / 2);
+
+            for bytes in &[bytes, b"not compressed".to_vec()] {
+                compressed.insert(
+                    DATA,
+                    Binary {
+                        subtype: BinarySubtype::Generic,
+                        bytes: bytes.clone(),
+                    },
+                );
+
+                assert!(matches!(
+                    decompress_section(&Bson::Document(compressed.clone())),
+                    Err(CompressionError::Io(_)) | Err(CompressionError::Deserialization(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_codec_is_an_error() {
+        let mut compressed = compress_section(&section(), Codec::Gzip).unwrap();
+        compressed.insert(COMPRESSION, "brotli");
+
+        assert!(matches!(
+            decompress_section(&Bson::Document(compressed)),
+            Err(CompressionError::UnknownCodec(name)) if name == "brotli"
+        ));
+    }
+}
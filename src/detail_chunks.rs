@@ -0,0 +1,383 @@
+use crate::lh_models::{FilmstripItem, NetworkRequest, Task};
+use crate::AuditDetail;
+use bson::{doc, oid::ObjectId, Bson, Document};
+use getset::{Getters, Setters};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+
+/// MongoDB's maximum BSON document size.
+pub const MAX_DOCUMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Headroom left in each chunk for its envelope fields.
+const CHUNK_OVERHEAD: usize = 16 * 1024;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "camelCase")]
+pub enum ChunkedTable {
+    ScreenshotThumbnails,
+    NetworkRequests,
+    MainThreadTasks,
+}
+
+impl ChunkedTable {
+    /// Tables in the order they are moved out, largest first.
+    pub fn all() -> [ChunkedTable; 3] {
+        [
+            ChunkedTable::ScreenshotThumbnails,
+            ChunkedTable::NetworkRequests,
+            ChunkedTable::MainThreadTasks,
+        ]
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct AuditDetailChunk {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    audit_detail_id: ObjectId,
+    table: ChunkedTable,
+    index: i32,
+    items: Vec<Bson>,
+}
+
+#[derive(Debug)]
+pub enum ChunkError {
+    Serialization(bson::ser::Error),
+    Deserialization(bson::de::Error),
+    TooLarge { size: usize, max_size: usize },
+    MissingChunk(ObjectId),
+    MissingTable(ChunkedTable),
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChunkError::Serialization(e) => write!(f, "failed to serialize audit detail: {}", e),
+            ChunkError::Deserialization(e) => write!(f, "failed to deserialize chunk: {}", e),
+            ChunkError::TooLarge { size, max_size } => write!(
+                f,
+                "document of {} bytes exceeds the limit of {} bytes",
+                size, max_size
+            ),
+            ChunkError::MissingChunk(id) => write!(f, "chunk {} is missing", id),
+            ChunkError::MissingTable(table) => {
+                write!(f, "audit detail has no {:?} table to reassemble", table)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+impl From<bson::ser::Error> for ChunkError {
+    fn from(e: bson::ser::Error) -> ChunkError {
+        ChunkError::Serialization(e)
+    }
+}
+
+impl From<bson::de::Error> for ChunkError {
+    fn from(e: bson::de::Error) -> ChunkError {
+        ChunkError::Deserialization(e)
+    }
+}
+
+/// Size in bytes of the value encoded as a BSON document.
+pub fn encoded_size<T: Serialize>(value: &T) -> Result<usize, ChunkError> {
+    document_size(&bson::to_document(value)?)
+}
+
+fn document_size(document: &Document) -> Result<usize, ChunkError> {
+    let mut bytes = Vec::new();
+    document.to_writer(&mut bytes)?;
+    Ok(bytes.len())
+}
+
+/// Moves table items out of the detail into chunks until the detail encodes
+/// within `max_size`. Details that already fit are returned unchanged, with
+/// no chunks. The detail is given an id if it has none, as chunks refer to it.
+pub fn split(
+    mut detail: AuditDetail,
+    max_size: usize,
+) -> Result<(AuditDetail, Vec<AuditDetailChunk>), ChunkError> {
+    if encoded_size(&detail)? <= max_size {
+        return Ok((detail, Vec::new()));
+    }
+
+    let audit_detail_id = detail.id.get_or_insert_with(ObjectId::new).clone();
+    let chunk_size = max_size.saturating_sub(CHUNK_OVERHEAD).max(1);
+    let mut chunks = Vec::new();
+
+    for table in &ChunkedTable::all() {
+        let items = take_items(&mut detail, *table)?;
+        chunks.extend(pack(&audit_detail_id, *table, items, chunk_size)?);
+
+        detail.chunk_ids = Some(chunks.iter().map(|c| c.id.clone()).collect());
+        if encoded_size(&detail)? <= max_size {
+            return Ok((detail, chunks));
+        }
+    }
+
+    Err(ChunkError::TooLarge {
+        size: encoded_size(&detail)?,
+        max_size,
+    })
+}
+
+/// Restores the items of a split detail from its chunks.
+pub fn reassemble(
+    mut detail: AuditDetail,
+    chunks: Vec<AuditDetailChunk>,
+) -> Result<AuditDetail, ChunkError> {
+    let chunk_ids = match detail.chunk_ids.take() {
+        Some(chunk_ids) => chunk_ids,
+        None => return Ok(detail),
+    };
+
+    let mut referenced = Vec::with_capacity(chunk_ids.len());
+    for chunk_id in chunk_ids {
+        let chunk = chunks
+            .iter()
+            .find(|c| c.id == chunk_id)
+            .ok_or(ChunkError::MissingChunk(chunk_id))?;
+        referenced.push(chunk);
+    }
+    referenced.sort_by_key(|c| (c.table, c.index));
+
+    for chunk in referenced {
+        put_items(&mut detail, chunk.table, &chunk.items)?;
+    }
+
+    Ok(detail)
+}
+
+/// Finds the chunks of a detail in the `auditDetailChunks` collection.
+pub fn chunks_filter(audit_detail_id: &ObjectId) -> Document {
+    doc! { "auditDetailId": audit_detail_id.clone() }
+}
+
+pub fn chunks_sort() -> Document {
+    doc! { "table": 1, "index": 1 }
+}
+
+fn pack(
+    audit_detail_id: &ObjectId,
+    table: ChunkedTable,
+    items: Vec<Bson>,
+    chunk_size: usize,
+) -> Result<Vec<AuditDetailChunk>, ChunkError> {
+    let mut chunks = Vec::new();
+    let mut current = Vec::new();
+    let mut current_size = 0;
+
+    for item in items {
+        let size = document_size(&doc! { "0": item.clone() })?;
+        if size > chunk_size {
+            return Err(ChunkError::TooLarge {
+                size,
+                max_size: chunk_size,
+            });
+        }
+        if current_size + size > chunk_size {
+            chunks.push(new_chunk(audit_detail_id, table, chunks.len(), current));
+            current = Vec::new();
+            current_size = 0;
+        }
+        current_size += size;
+        current.push(item);
+    }
+    if !current.is_empty() {
+        chunks.push(new_chunk(audit_detail_id, table, chunks.len(), current));
+    }
+
+    Ok(chunks)
+}
+
+fn new_chunk(
+    audit_detail_id: &ObjectId,
+    table: ChunkedTable,
+    index: usize,
+    items: Vec<Bson>,
+) -> AuditDetailChunk {
+    AuditDetailChunk {
+        id: ObjectId::new(),
+        audit_detail_id: audit_detail_id.clone(),
+        table,
+        index: index as i32,
+        items,
+    }
+}
+
+fn take_items(detail: &mut AuditDetail, table: ChunkedTable) -> Result<Vec<Bson>, ChunkError> {
+    match table {
+        ChunkedTable::ScreenshotThumbnails => to_bson_items(
+            detail
+                .screenshot_thumbnails
+                .as_mut()
+                .map(|a| std::mem::take(a.details_mut().items_mut())),
+        ),
+        ChunkedTable::NetworkRequests => to_bson_items(
+            detail
+                .network_requests
+                .as_mut()
+                .map(|a| std::mem::take(a.details_mut().items_mut())),
+        ),
+        ChunkedTable::MainThreadTasks => to_bson_items(
+            detail
+                .main_thread_tasks
+                .as_mut()
+                .map(|a| std::mem::take(a.details_mut().items_mut())),
+        ),
+    }
+}
+
+fn put_items(
+    detail: &mut AuditDetail,
+    table: ChunkedTable,
+    items: &[Bson],
+) -> Result<(), ChunkError> {
+    match table {
+        ChunkedTable::ScreenshotThumbnails => extend_items::<FilmstripItem>(
+            detail
+                .screenshot_thumbnails
+                .as_mut()
+                .map(|a| a.details_mut().items_mut()),
+            table,
+            items,
+        ),
+        ChunkedTable::NetworkRequests => extend_items::<NetworkRequest>(
+            detail
+                .network_requests
+                .as_mut()
+                .map(|a| a.details_mut().items_mut()),
+            table,
+            items,
+        ),
+        ChunkedTable::MainThreadTasks => extend_items::<Task>(
+            detail
+                .main_thread_tasks
+                .as_mut()
+                .map(|a| a.details_mut().items_mut()),
+            table,
+            items,
+        ),
+    }
+}
+
+fn to_bson_items<T: Serialize>(items: Option<Vec<T>>) -> Result<Vec<Bson>, ChunkError> {
+    let mut bson_items = Vec::new();
+    for item in items.unwrap_or_default() {
+        bson_items.push(bson::to_bson(&item)?);
+    }
+    Ok(bson_items)
+}
+
+fn extend_items<T: DeserializeOwned>(
+    target: Option<&mut Vec<T>>,
+    table: ChunkedTable,
+    items: &[Bson],
+) -> Result<(), ChunkError> {
+    let target = target.ok_or(ChunkError::MissingTable(table))?;
+    for item in items {
+        target.push(bson::from_bson(item.clone())?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lh_models::{AuditTable, Table};
+
+    /// A detail with `count` network requests of roughly `url_len` bytes each.
+    fn detail(count: usize, url_len: usize) -> AuditDetail {
+        let requests = (0..count)
+            .map(|i| {
+                let mut request = NetworkRequest::default();
+                request.set_url(format!("https://example.com/{}/{}", i, "a".repeat(url_len)));
+                request.set_resource_size(i as i64);
+                request
+            })
+            .collect();
+        let mut table = Table::default();
+        table.set_items(requests);
+        let mut tasks = Table::default();
+        tasks.set_items(vec![Task::default(); 8]);
+
+        let mut network_requests = AuditTable::default();
+        network_requests.set_details(table);
+        let mut main_thread_tasks = AuditTable::default();
+        main_thread_tasks.set_details(tasks);
+
+        let mut detail = AuditDetail::default();
+        detail.set_network_requests(Some(network_requests));
+        detail.set_main_thread_tasks(Some(main_thread_tasks));
+        detail
+    }
+
+    fn request_count(detail: &AuditDetail) -> usize {
+        detail
+            .network_requests()
+            .as_ref()
+            .map_or(0, |a| a.details().items().len())
+    }
+
+    #[test]
+    fn detail_within_limit_is_not_split() {
+        let (detail, chunks) = split(detail(10, 100), MAX_DOCUMENT_SIZE).unwrap();
+
+        assert!(chunks.is_empty());
+        assert!(detail.id().is_none());
+        assert!(detail.chunk_ids().is_none());
+        assert_eq!(request_count(&detail), 10);
+    }
+
+    #[test]
+    fn split_and_reassemble_round_trip() {
+        let max_size = 64 * 1024;
+        let original = detail(300, 500);
+        assert!(encoded_size(&original).unwrap() > max_size);
+
+        let (split_detail, mut chunks) = split(original.clone(), max_size).unwrap();
+
+        assert!(encoded_size(&split_detail).unwrap() <= max_size);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks {
+            assert_eq!(Some(chunk.audit_detail_id()), split_detail.id().as_ref());
+            assert!(encoded_size(chunk).unwrap() <= max_size);
+        }
+
+        chunks.reverse();
+        let reassembled = reassemble(split_detail.clone(), chunks).unwrap();
+        let mut expected = original;
+        expected.set_id(split_detail.id().clone());
+
+        assert!(reassembled.chunk_ids().is_none());
+        assert_eq!(
+            bson::to_document(&reassembled).unwrap(),
+            bson::to_document(&expected).unwrap()
+        );
+    }
+
+    #[test]
+    fn single_oversize_item_is_too_large() {
+        let max_size = 64 * 1024;
+
+        match split(detail(1, 2 * max_size), max_size) {
+            Err(ChunkError::TooLarge { size, .. }) => assert!(size > max_size),
+            other => panic!("expected a too large error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reassemble_requires_every_chunk() {
+        let (split_detail, mut chunks) = split(detail(300, 500), 64 * 1024).unwrap();
+        let missing = chunks.pop().unwrap();
+
+        match reassemble(split_detail, chunks) {
+            Err(ChunkError::MissingChunk(id)) => assert_eq!(&id, missing.id()),
+            other => panic!("expected a missing chunk error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use crate::lh_models::{Audit, NodeValue, Opportunity};
+use crate::AuditDetail;
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// What changed from one run's details to another's.
+#[derive(Deserialize, Serialize, Debug, Getters, Default, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct DetailDiff {
+    added_requests: Vec<String>,
+    removed_requests: Vec<String>,
+    resource_changes: Vec<ResourceChange>,
+    added_third_parties: Vec<String>,
+    removed_third_parties: Vec<String>,
+    appeared_opportunities: Vec<OpportunityChange>,
+    resolved_opportunities: Vec<OpportunityChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lcp_element: Option<LcpElementChange>,
+}
+
+impl DetailDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_requests.is_empty()
+            && self.removed_requests.is_empty()
+            && self.resource_changes.is_empty()
+            && self.added_third_parties.is_empty()
+            && self.removed_third_parties.is_empty()
+            && self.appeared_opportunities.is_empty()
+            && self.resolved_opportunities.is_empty()
+            && self.lcp_element.is_none()
+    }
+}
+
+/// Request count and transfer size of one resource type, as in
+/// `resource_summary`. Types missing from a run count as zero.
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceChange {
+    resource_type: String,
+    label: String,
+    request_count_before: i32,
+    request_count_after: i32,
+    transfer_size_before: i64,
+    transfer_size_after: i64,
+}
+
+impl ResourceChange {
+    pub fn transfer_size_delta(&self) -> i64 {
+        self.transfer_size_after - self.transfer_size_before
+    }
+
+    pub fn request_count_delta(&self) -> i32 {
+        self.request_count_after - self.request_count_before
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct OpportunityChange {
+    id: String,
+    title: String,
+    /// Estimated savings of the run where the opportunity is open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    savings_ms: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct LcpElementChange {
+    before: Option<NodeValue>,
+    after: Option<NodeValue>,
+}
+
+impl AuditDetail {
+    /// The opportunity audits present in this run.
+    pub fn opportunities(&self) -> Vec<&Audit<Opportunity>> {
+        [
+            &self.uses_responsive_images,
+            &self.uses_optimized_images,
+            &self.uses_webp_images,
+            &self.offscreen_images,
+            &self.uses_http2,
+            &self.unminified_css,
+            &self.unminified_javascript,
+            &self.unused_css_rules,
+            &self.unused_javascript,
+            &self.render_blocking_resources,
+        ]
+        .iter()
+        .filter_map(|audit| audit.as_ref())
+        .collect()
+    }
+
+    /// Changes from `self`, the earlier run, to `other`.
+    pub fn diff(&self, other: &AuditDetail) -> DetailDiff {
+        let before = request_urls(self);
+        let after = request_urls(other);
+        let before_parties = third_parties(self);
+        let after_parties = third_parties(other);
+        let before_opportunities = open_opportunities(self);
+        let after_opportunities = open_opportunities(other);
+
+        DetailDiff {
+            added_requests: after.difference(&before).cloned().collect(),
+            removed_requests: before.difference(&after).cloned().collect(),
+            resource_changes: resource_changes(self, other),
+            added_third_parties: after_parties.difference(&before_parties).cloned().collect(),
+            removed_third_parties: before_parties.difference(&after_parties).cloned().collect(),
+            appeared_opportunities: after_opportunities
+                .iter()
+                .filter(|(id, _)| !before_opportunities.contains_key(*id))
+                .map(|(_, change)| change.clone())
+                .collect(),
+            resolved_opportunities: before_opportunities
+                .iter()
+                .filter(|(id, _)| !after_opportunities.contains_key(*id))
+                .map(|(_, change)| change.clone())
+                .collect(),
+            lcp_element: lcp_element_change(self, other),
+        }
+    }
+}
+
+fn request_urls(detail: &AuditDetail) -> BTreeSet<String> {
+    detail
+        .network_requests
+        .iter()
+        .flat_map(|audit| audit.details().items())
+        .map(|request| request.url().clone())
+        .collect()
+}
+
+fn third_parties(detail: &AuditDetail) -> BTreeSet<String> {
+    detail
+        .third_party_summary
+        .iter()
+        .flat_map(|audit| audit.details().items())
+        .map(|party| party.entity().text().clone())
+        .collect()
+}
+
+/// Opportunities with items that Lighthouse does not consider passed.
+fn open_opportunities(detail: &AuditDetail) -> BTreeMap<String, OpportunityChange> {
+    detail
+        .opportunities()
+        .into_iter()
+        .filter(|audit| {
+            !audit.details().items().is_empty() && audit.score().is_none_or(|score| score < 0.9)
+        })
+        .map(|audit| {
+            let change = OpportunityChange {
+                id: audit.id().clone(),
+                title: audit.title().clone(),
+                savings_ms: *audit.numeric_value(),
+            };
+            (audit.id().clone(), change)
+        })
+        .collect()
+}
+
+fn resource_changes(before: &AuditDetail, after: &AuditDetail) -> Vec<ResourceChange> {
+    let mut changes: BTreeMap<String, ResourceChange> = BTreeMap::new();
+    for (detail, is_before) in [(before, true), (after, false)].iter() {
+        let resources = detail
+            .resource_summary
+            .iter()
+            .flat_map(|audit| audit.details().items());
+        for resource in resources {
+            let change = changes
+                .entry(resource.resource_type().clone())
+                .or_insert_with(|| ResourceChange {
+                    resource_type: resource.resource_type().clone(),
+                    label: resource.label().clone(),
+                    request_count_before: 0,
+                    request_count_after: 0,
+                    transfer_size_before: 0,
+                    transfer_size_after: 0,
+                });
+            let transfer_size = resource.transfer_size().unwrap_or(0);
+            if *is_before {
+                change.request_count_before = *resource.request_count();
+                change.transfer_size_before = transfer_size;
+            } else {
+                change.request_count_after = *resource.request_count();
+                change.transfer_size_after = transfer_size;
+            }
+        }
+    }
+
+    changes
+        .into_values()
+        .filter(|c| c.transfer_size_delta() != 0 || c.request_count_delta() != 0)
+        .collect()
+}
+
+fn lcp_element(detail: &AuditDetail) -> Option<&NodeValue> {
+    detail
+        .largest_contentful_paint_element
+        .as_ref()
+        .and_then(|audit| audit.details().items().first())
+        .map(|item| item.node())
+}
+
+/// Elements are compared by selector, then path, then snippet.
+fn lcp_element_change(before: &AuditDetail, after: &AuditDetail) -> Option<LcpElementChange> {
+    let before = lcp_element(before);
+    let after = lcp_element(after);
+    let key = |node: Option<&NodeValue>| {
+        node.map(|node| {
+            (
+                node.selector().clone(),
+                node.path().clone(),
+                node.snippet().clone(),
+            )
+        })
+    };
+    if key(before) == key(after) {
+        return None;
+    }
+
+    Some(LcpElementChange {
+        before: before.cloned(),
+        after: after.cloned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+    use serde_json::{json, Value};
+
+    /// An audit with the given details; optional fields are left out.
+    fn audit<T: DeserializeOwned>(id: &str, extra: Value, details: Value) -> T {
+        let mut audit = json!({ "id": id, "title": id, "description": "", "details": details });
+        if let (Some(audit), Value::Object(extra)) = (audit.as_object_mut(), extra) {
+            audit.extend(extra);
+        }
+        serde_json::from_value(audit).unwrap()
+    }
+
+    fn with_requests(mut detail: AuditDetail, urls: &[&str]) -> AuditDetail {
+        let items: Vec<Value> = urls
+            .iter()
+            .map(|url| json!({ "url": url, "resourceSize": 0, "statusCode": 200, "mimeType": "" }))
+            .collect();
+        detail.set_network_requests(Some(audit(
+            "network-requests",
+            json!({}),
+            json!({ "items": items }),
+        )));
+        detail
+    }
+
+    fn with_resources(mut detail: AuditDetail, resources: Value) -> AuditDetail {
+        detail.set_resource_summary(Some(audit(
+            "resource-summary",
+            json!({}),
+            json!({ "items": resources }),
+        )));
+        detail
+    }
+
+    fn opportunity(
+        id: &str,
+        score: Option<f64>,
+        savings_ms: f64,
+        items: usize,
+    ) -> Audit<Opportunity> {
+        let items: Vec<Value> = (0..items)
+            .map(|i| json!({ "url": format!("https://example.com/{}.js", i) }))
+            .collect();
+        audit(
+            id,
+            json!({ "score": score, "numericValue": savings_ms }),
+            json!({ "items": items }),
+        )
+    }
+
+    fn with_lcp_element(mut detail: AuditDetail, selector: &str) -> AuditDetail {
+        detail.set_largest_contentful_paint_element(Some(audit(
+            "largest-contentful-paint-element",
+            json!({}),
+            json!({ "items": [{ "node": { "type": "node", "selector": selector } }] }),
+        )));
+        detail
+    }
+
+    #[test]
+    fn identical_details_have_no_changes() {
+        let detail = with_lcp_element(
+            with_requests(AuditDetail::default(), &["https://example.com/"]),
+            "main > img",
+        );
+
+        assert!(detail.diff(&detail.clone()).is_empty());
+    }
+
+    #[test]
+    fn added_and_removed_requests() {
+        let before = with_requests(
+            AuditDetail::default(),
+            &["https://example.com/", "https://example.com/old.js"],
+        );
+        let after = with_requests(
+            AuditDetail::default(),
+            &["https://example.com/", "https://cdn.example.com/new.js"],
+        );
+
+        let diff = before.diff(&after);
+
+        assert_eq!(
+            diff.added_requests(),
+            &vec!["https://cdn.example.com/new.js".to_owned()]
+        );
+        assert_eq!(
+            diff.removed_requests(),
+            &vec!["https://example.com/old.js".to_owned()]
+        );
+    }
+
+    #[test]
+    fn resource_deltas_cover_types_missing_from_either_run() {
+        let before = with_resources(
+            AuditDetail::default(),
+            json!([
+                { "resourceType": "script", "label": "Script", "requestCount": 10, "transferSize": 100000 },
+                { "resourceType": "image", "label": "Image", "requestCount": 4, "transferSize": 50000 },
+                { "resourceType": "media", "label": "Media", "requestCount": 1, "transferSize": 9000 },
+            ]),
+        );
+        let after = with_resources(
+            AuditDetail::default(),
+            json!([
+                { "resourceType": "font", "label": "Font", "requestCount": 2, "transferSize": 30000 },
+                { "resourceType": "image", "label": "Image", "requestCount": 4, "transferSize": 50000 },
+                { "resourceType": "script", "label": "Script", "requestCount": 12, "transferSize": 150000 },
+            ]),
+        );
+
+        let changes = before.diff(&after).resource_changes().clone();
+        let deltas: Vec<(&str, i32, i64)> = changes
+            .iter()
+            .map(|c| {
+                (
+                    c.resource_type().as_str(),
+                    c.request_count_delta(),
+                    c.transfer_size_delta(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            deltas,
+            vec![
+                ("font", 2, 30000),
+                ("media", -1, -9000),
+                ("script", 2, 50000)
+            ]
+        );
+    }
+
+    #[test]
+    fn appeared_and_resolved_opportunities() {
+        let mut before = AuditDetail::default();
+        before.set_unused_javascript(Some(opportunity("unused-javascript", Some(0.5), 450.0, 2)));
+        before.set_offscreen_images(Some(opportunity("offscreen-images", Some(0.95), 20.0, 1)));
+        let mut after = AuditDetail::default();
+        after.set_unused_javascript(Some(opportunity("unused-javascript", Some(1.0), 0.0, 0)));
+        after.set_offscreen_images(Some(opportunity("offscreen-images", Some(0.95), 20.0, 1)));
+        after.set_render_blocking_resources(Some(opportunity(
+            "render-blocking-resources",
+            None,
+            300.0,
+            1,
+        )));
+
+        let diff = before.diff(&after);
+
+        assert_eq!(
+            diff.appeared_opportunities(),
+            &vec![OpportunityChange {
+                id: "render-blocking-resources".to_owned(),
+                title: "render-blocking-resources".to_owned(),
+                savings_ms: Some(300.0),
+            }]
+        );
+        assert_eq!(
+            diff.resolved_opportunities(),
+            &vec![OpportunityChange {
+                id: "unused-javascript".to_owned(),
+                title: "unused-javascript".to_owned(),
+                savings_ms: Some(450.0),
+            }]
+        );
+    }
+
+    #[test]
+    fn lcp_element_changes() {
+        let hero = with_lcp_element(AuditDetail::default(), "main > img.hero");
+        let heading = with_lcp_element(AuditDetail::default(), "main > h1");
+
+        let change = hero.diff(&heading).lcp_element().clone().unwrap();
+        assert_eq!(
+            change.before().as_ref().unwrap().selector().as_deref(),
+            Some("main > img.hero")
+        );
+        assert_eq!(
+            change.after().as_ref().unwrap().selector().as_deref(),
+            Some("main > h1")
+        );
+
+        let appeared = AuditDetail::default()
+            .diff(&hero)
+            .lcp_element()
+            .clone()
+            .unwrap();
+        assert!(appeared.before().is_none());
+        assert!(appeared.after().is_some());
+
+        assert!(hero.diff(&hero.clone()).lcp_element().is_none());
+    }
+}
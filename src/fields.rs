@@ -0,0 +1,105 @@
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// Serialized field names of a struct, as produced by its serde attributes.
+pub fn serde_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields = None;
+    let _ = T::deserialize(FieldsDeserializer {
+        fields: &mut fields,
+    });
+    fields.unwrap_or(&[])
+}
+
+/// Maps a Rust field name (`site_id`, `id`) to its serialized name (`siteId`,
+/// `_id`), so queries follow the serde renames instead of repeating them.
+///
+/// Returns `None` if the struct has no such field.
+pub fn field_name<'de, T: Deserialize<'de>>(rust_name: &str) -> Option<&'static str> {
+    let normalized = normalize(rust_name);
+    serde_fields::<T>()
+        .iter()
+        .find(|field| normalize(field) == normalized)
+        .copied()
+}
+
+/// `field_name` for the field names written out in this crate, which the
+/// tests cover; a missing field there is a bug rather than bad input.
+pub(crate) fn field<'de, T: Deserialize<'de>>(rust_name: &'static str) -> &'static str {
+    field_name::<T>(rust_name)
+        .unwrap_or_else(|| panic!("{} has no field {}", std::any::type_name::<T>(), rust_name))
+}
+
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_')
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+struct FieldsDeserializer<'a> {
+    fields: &'a mut Option<&'static [&'static str]>,
+}
+
+impl<'de, 'a> Deserializer<'de> for FieldsDeserializer<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.fields = Some(fields);
+        Err(de::Error::custom("fields captured"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditProfile, AuditSummary};
+
+    #[test]
+    fn serde_fields_follow_renames() {
+        let fields = serde_fields::<AuditSummary>();
+
+        assert_eq!(fields[0], "_id");
+        assert!(fields.contains(&"siteId"));
+        assert!(fields.contains(&"auditDetailId"));
+        assert!(serde_fields::<String>().is_empty());
+    }
+
+    #[test]
+    fn field_name_maps_rust_names_to_serialized_names() {
+        assert_eq!(field_name::<AuditSummary>("id"), Some("_id"));
+        assert_eq!(field_name::<AuditSummary>("site_id"), Some("siteId"));
+        assert_eq!(field_name::<AuditSummary>("fetch_time"), Some("fetchTime"));
+        assert_eq!(
+            field_name::<AuditProfile>("lighthouse_version"),
+            Some("lighthouseVersion")
+        );
+    }
+
+    #[test]
+    fn field_name_is_none_for_unknown_fields() {
+        assert_eq!(field_name::<AuditSummary>("no_such_field"), None);
+        assert_eq!(field_name::<AuditSummary>(""), None);
+        assert_eq!(field_name::<String>("len"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no field no_such_field")]
+    fn field_panics_for_unknown_fields() {
+        field::<AuditSummary>("no_such_field");
+    }
+}
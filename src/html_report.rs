@@ -0,0 +1,309 @@
+use crate::format::{escape_html, format_bytes, format_ms};
+use crate::lh_models::{Audit, Opportunity};
+use crate::metrics::Metric;
+pub use crate::report::ranked_opportunities;
+use crate::AuditDetail;
+use std::fmt::Write;
+
+const STYLE: &str = "\
+body{font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',Roboto,sans-serif;color:#202124;margin:0 auto;max-width:960px;padding:24px}\
+h1{font-size:20px;margin:0 0 4px}h2{font-size:16px;margin:32px 0 8px;border-bottom:1px solid #dadce0;padding-bottom:4px}\
+.meta{color:#5f6368;font-size:13px;word-break:break-all}\
+.summary{display:flex;align-items:center;gap:32px;margin-top:16px}\
+.vitals{display:grid;grid-template-columns:repeat(3,1fr);gap:12px;flex:1}\
+.vital{border-left:4px solid;padding:4px 8px}.vital .label{font-size:12px;color:#5f6368}.vital .value{font-size:20px}\
+table{border-collapse:collapse;width:100%;font-size:13px}th,td{text-align:left;padding:4px 8px;border-bottom:1px solid #f1f3f4}\
+td.num,th.num{text-align:right;white-space:nowrap}\
+.url{max-width:480px;overflow:hidden;text-overflow:ellipsis;white-space:nowrap}\
+details summary{cursor:pointer}\
+.filmstrip{display:flex;gap:8px;overflow-x:auto}.filmstrip figure{margin:0;text-align:center;font-size:11px;color:#5f6368}\
+.filmstrip img{display:block;height:120px;border:1px solid #dadce0}\
+.waterfall td.bar{width:50%;position:relative}.waterfall span{position:absolute;top:6px;height:8px;border-radius:2px}";
+
+/// A self-contained HTML page for the run: styles are inline and filmstrip
+/// frames are embedded as data URLs. Frames moved to a blob store are left
+/// out; restore them with `blobs::hydrate_filmstrip` first.
+pub fn render(detail: &AuditDetail) -> String {
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n<title>Lighthouse report: {}</title>\n<style>{}</style>\n</head>\n<body>\n",
+        escape_html(detail.final_url()),
+        STYLE
+    );
+    let _ = write!(
+        html,
+        "<h1>{}</h1>\n<div class=\"meta\">Fetched {} with Lighthouse {}</div>\n",
+        escape_html(detail.final_url()),
+        escape_html(detail.fetch_time()),
+        escape_html(detail.lighthouse_version())
+    );
+
+    html.push_str("<div class=\"summary\">\n");
+    html.push_str(&gauge(*detail.categories().performance().score()));
+    html.push_str(&vitals(detail));
+    html.push_str("</div>\n");
+    html.push_str(&opportunities(&ranked_opportunities(detail)));
+    html.push_str(&filmstrip(detail));
+    html.push_str(&resource_summary(detail));
+    html.push_str(&third_parties(detail));
+    html.push_str(&waterfall(detail));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+fn gauge(score: f64) -> String {
+    let circumference = 2.0 * std::f64::consts::PI * 50.0;
+    format!(
+        concat!(
+            "<svg width=\"120\" height=\"120\" viewBox=\"0 0 120 120\" role=\"img\" aria-label=\"Performance score {score}\">",
+            "<circle cx=\"60\" cy=\"60\" r=\"50\" fill=\"none\" stroke=\"#f1f3f4\" stroke-width=\"10\"/>",
+            "<circle cx=\"60\" cy=\"60\" r=\"50\" fill=\"none\" stroke=\"{color}\" stroke-width=\"10\" stroke-linecap=\"round\" ",
+            "stroke-dasharray=\"{length:.1} {circumference:.1}\" transform=\"rotate(-90 60 60)\"/>",
+            "<text x=\"60\" y=\"70\" text-anchor=\"middle\" font-size=\"32\" fill=\"{color}\">{score}</text>",
+            "</svg>\n"
+        ),
+        score = Metric::Score.format(score),
+        color = Metric::Score.rating(score).color(),
+        length = score.clamp(0.0, 1.0) * circumference,
+        circumference = circumference,
+    )
+}
+
+fn vitals(detail: &AuditDetail) -> String {
+    let mut html = String::from("<div class=\"vitals\">\n");
+    for metric in Metric::web_vitals().iter() {
+        if let Some(value) = metric.web_vital_value(detail.web_vitals()) {
+            let _ = writeln!(
+                html,
+                "<div class=\"vital\" style=\"border-color:{}\"><div class=\"label\">{}</div><div class=\"value\">{}</div></div>",
+                metric.rating(value).color(),
+                escape_html(metric.label()),
+                escape_html(&metric.format(value))
+            );
+        }
+    }
+    html.push_str("</div>\n");
+    html
+}
+
+fn opportunities(ranked: &[(&Audit<Opportunity>, f64)]) -> String {
+    if ranked.is_empty() {
+        return String::new();
+    }
+
+    let mut html = String::from("<h2>Opportunities</h2>\n");
+    for (audit, savings) in ranked {
+        let _ = write!(
+            html,
+            "<details>\n<summary><strong>{}</strong> — est. savings {}</summary>\n<table>\n<tr><th>URL</th><th class=\"num\">Size</th><th class=\"num\">Potential savings</th></tr>\n",
+            escape_html(audit.title()),
+            format_ms(*savings)
+        );
+        for item in audit.details().items() {
+            let _ = writeln!(
+                html,
+                "<tr><td class=\"url\" title=\"{url}\">{url}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+                item.total_bytes().map(format_bytes).unwrap_or_default(),
+                item.wasted_bytes()
+                    .map(|bytes| format_bytes(bytes as i64))
+                    .unwrap_or_default(),
+                url = escape_html(item.url()),
+            );
+        }
+        html.push_str("</table>\n</details>\n");
+    }
+    html
+}
+
+fn filmstrip(detail: &AuditDetail) -> String {
+    let frames: Vec<String> = detail
+        .screenshot_thumbnails()
+        .iter()
+        .flat_map(|audit| audit.details().items())
+        .filter(|item| item.data().starts_with("data:image/"))
+        .map(|item| {
+            format!(
+                "<figure><img src=\"{}\" alt=\"Frame at {ms}\"><figcaption>{ms}</figcaption></figure>",
+                escape_html(item.data()),
+                ms = format_ms(*item.timing() as f64)
+            )
+        })
+        .collect();
+    if frames.is_empty() {
+        return String::new();
+    }
+
+    format!(
+        "<h2>Filmstrip</h2>\n<div class=\"filmstrip\">\n{}\n</div>\n",
+        frames.join("\n")
+    )
+}
+
+fn resource_summary(detail: &AuditDetail) -> String {
+    let resources = match detail.resource_summary() {
+        Some(audit) if !audit.details().items().is_empty() => audit.details().items(),
+        _ => return String::new(),
+    };
+
+    let mut html = String::from(
+        "<h2>Resources</h2>\n<table>\n<tr><th>Type</th><th class=\"num\">Requests</th><th class=\"num\">Transfer size</th></tr>\n",
+    );
+    for resource in resources {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+            escape_html(resource.label()),
+            resource.request_count(),
+            resource
+                .transfer_size()
+                .map(format_bytes)
+                .unwrap_or_default()
+        );
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn third_parties(detail: &AuditDetail) -> String {
+    let mut parties: Vec<_> = match detail.third_party_summary() {
+        Some(audit) if !audit.details().items().is_empty() => {
+            audit.details().items().iter().collect()
+        }
+        _ => return String::new(),
+    };
+    parties.sort_by(|a, b| {
+        b.blocking_time()
+            .partial_cmp(a.blocking_time())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let mut html = String::from(
+        "<h2>Third parties</h2>\n<table>\n<tr><th>Entity</th><th class=\"num\">Transfer size</th><th class=\"num\">Main-thread time</th><th class=\"num\">Blocking time</th></tr>\n",
+    );
+    for party in parties {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+            escape_html(party.entity().text()),
+            format_bytes(*party.transfer_size()),
+            format_ms(*party.main_thread_time()),
+            format_ms(*party.blocking_time())
+        );
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Requests in start order with a bar from start to end time, scaled to the
+/// last request to finish.
+fn waterfall(detail: &AuditDetail) -> String {
+    let mut requests: Vec<_> = match detail.network_requests() {
+        Some(audit) => audit
+            .details()
+            .items()
+            .iter()
+            .filter(|request| request.start_time().is_some())
+            .collect(),
+        None => return String::new(),
+    };
+    if requests.is_empty() {
+        return String::new();
+    }
+    requests.sort_by(|a, b| {
+        a.start_time()
+            .partial_cmp(b.start_time())
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    let start = requests
+        .iter()
+        .filter_map(|request| *request.start_time())
+        .fold(f64::INFINITY, f64::min);
+    let end = requests
+        .iter()
+        .filter_map(|request| request.end_time().or(*request.start_time()))
+        .fold(start, f64::max);
+    let span = (end - start).max(1.0);
+
+    let mut html = String::from(
+        "<h2>Network requests</h2>\n<table class=\"waterfall\">\n<tr><th>URL</th><th class=\"num\">Status</th><th class=\"num\">Size</th><th>Timeline</th></tr>\n",
+    );
+    for request in requests {
+        let request_start = request.start_time().unwrap_or(start);
+        let request_end = request.end_time().unwrap_or(request_start);
+        let left = (request_start - start) / span * 100.0;
+        let width = ((request_end - request_start) / span * 100.0).max(0.5);
+        let _ = writeln!(
+            html,
+            "<tr><td class=\"url\" title=\"{url}\">{url}</td><td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"bar\"><span style=\"left:{:.2}%;width:{:.2}%;background:{}\" title=\"{} – {}\"></span></td></tr>",
+            request.status_code(),
+            request.transfer_size().map(format_bytes).unwrap_or_default(),
+            left,
+            width.min(100.0 - left),
+            resource_color(request.resource_type().as_deref()),
+            format_ms(request_start - start),
+            format_ms(request_end - start),
+            url = escape_html(request.url()),
+        );
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+fn resource_color(resource_type: Option<&str>) -> &'static str {
+    match resource_type {
+        Some("Document") => "#1a73e8",
+        Some("Script") => "#e8710a",
+        Some("Stylesheet") => "#9334e6",
+        Some("Image") => "#188038",
+        Some("Font") => "#d01884",
+        Some("Media") => "#12b5cb",
+        _ => "#9aa0a6",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detail() -> AuditDetail {
+        serde_json::from_str(include_str!("../tests/fixtures/audit_detail.json")).unwrap()
+    }
+
+    #[test]
+    fn render_matches_snapshot() {
+        assert_eq!(
+            render(&detail()),
+            include_str!("../tests/fixtures/html_report/report.html")
+        );
+    }
+
+    #[test]
+    fn render_escapes_report_text() {
+        let html = render(&detail());
+
+        assert!(html.contains("<h1>https://example.com/search?q=shoes&amp;sort=price</h1>"));
+        assert!(html.contains("<td>Ads &lt;Partner&gt; &amp; Co</td>"));
+        assert!(html.contains("gtm.js?id=GTM-&quot;x&quot;"));
+        assert!(!html.contains("<Partner>"));
+    }
+
+    #[test]
+    fn render_leaves_out_externalised_frames() {
+        let html = render(&detail());
+
+        assert_eq!(html.matches("<figure>").count(), 2);
+        assert!(!html.contains("Frame at 600 ms"));
+    }
+
+    #[test]
+    fn render_without_audits_has_no_sections() {
+        let html = render(&AuditDetail::default());
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("aria-label=\"Performance score 0\""));
+        assert!(!html.contains("<h2>"));
+        assert!(html.ends_with("</body>\n</html>\n"));
+    }
+}
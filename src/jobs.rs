@@ -0,0 +1,285 @@
+use crate::ScoreParameters;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Duration, Utc};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::convert::TryFrom;
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreJob {
+    #[serde(rename = "_id")]
+    id: ObjectId,
+    idempotency_key: String,
+    priority: JobPriority,
+    attempt: i32,
+    max_retries: i32,
+    backoff: BackoffPolicy,
+    enqueued_at: bson::DateTime,
+    parameters: ScoreParameters,
+}
+
+impl ScoreJob {
+    pub fn new(parameters: ScoreParameters) -> Result<ScoreJob, bson::ser::Error> {
+        Ok(ScoreJob {
+            id: ObjectId::new(),
+            idempotency_key: idempotency_key(&parameters)?,
+            priority: JobPriority::default(),
+            attempt: 0,
+            max_retries: 3,
+            backoff: BackoffPolicy::default(),
+            enqueued_at: bson::DateTime(Utc::now()),
+            parameters,
+        })
+    }
+
+    /// Marks the start of another attempt; `attempt` is 1 for the first run.
+    pub fn start_attempt(&mut self) -> i32 {
+        self.attempt += 1;
+        self.attempt
+    }
+
+    pub fn can_retry(&self) -> bool {
+        self.attempt <= self.max_retries
+    }
+
+    /// Returns when the current (failed) attempt should be retried, or `None`
+    /// if the failure is permanent, the retries are exhausted or the time is
+    /// out of range. A `Retry-After` longer than the policy's maximum delay
+    /// is capped at that maximum.
+    pub fn next_retry_at(&self, failure: &JobFailure, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.can_retry() {
+            return None;
+        }
+
+        let delay = match failure {
+            JobFailure::Permanent => return None,
+            JobFailure::Transient | JobFailure::Timeout => self.backoff.delay(self.attempt),
+            JobFailure::RateLimited { retry_after_ms } => {
+                let delay = self.backoff.delay(self.attempt);
+                match retry_after_ms {
+                    Some(retry_after_ms) => {
+                        let max_delay_ms = self.backoff.max_delay_ms();
+                        delay.max(milliseconds((*retry_after_ms).min(max_delay_ms)))
+                    }
+                    None => delay,
+                }
+            }
+        };
+
+        now.checked_add_signed(delay)
+    }
+}
+
+/// Stored as its rank, 0 to 2, so sorting on the field orders jobs by
+/// urgency.
+#[derive(
+    Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(into = "i32", try_from = "i32")]
+pub enum JobPriority {
+    Low = 0,
+    #[default]
+    Normal = 1,
+    High = 2,
+}
+
+impl From<JobPriority> for i32 {
+    fn from(priority: JobPriority) -> i32 {
+        priority as i32
+    }
+}
+
+impl TryFrom<i32> for JobPriority {
+    type Error = String;
+
+    fn try_from(rank: i32) -> Result<JobPriority, String> {
+        match rank {
+            0 => Ok(JobPriority::Low),
+            1 => Ok(JobPriority::Normal),
+            2 => Ok(JobPriority::High),
+            _ => Err(format!("invalid job priority {}", rank)),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum BackoffPolicy {
+    #[serde(rename_all = "camelCase")]
+    Fixed { delay_ms: i64 },
+    #[serde(rename_all = "camelCase")]
+    Exponential {
+        initial_delay_ms: i64,
+        multiplier: f64,
+        max_delay_ms: i64,
+    },
+}
+
+impl BackoffPolicy {
+    /// The longest delay the policy produces.
+    pub fn max_delay_ms(&self) -> i64 {
+        match self {
+            BackoffPolicy::Fixed { delay_ms } => *delay_ms,
+            BackoffPolicy::Exponential { max_delay_ms, .. } => *max_delay_ms,
+        }
+    }
+
+    /// Delay before retrying after the given (1-based) failed attempt.
+    pub fn delay(&self, attempt: i32) -> Duration {
+        match self {
+            BackoffPolicy::Fixed { delay_ms } => milliseconds(*delay_ms),
+            BackoffPolicy::Exponential {
+                initial_delay_ms,
+                multiplier,
+                max_delay_ms,
+            } => {
+                let exponent = (attempt - 1).max(0);
+                let delay_ms = *initial_delay_ms as f64 * multiplier.powi(exponent);
+                milliseconds(delay_ms.min(*max_delay_ms as f64) as i64)
+            }
+        }
+    }
+}
+
+/// Stored delays are not trusted: negative ones count as no delay.
+fn milliseconds(ms: i64) -> Duration {
+    Duration::milliseconds(ms.max(0))
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> BackoffPolicy {
+        BackoffPolicy::Exponential {
+            initial_delay_ms: 30_000,
+            multiplier: 2.0,
+            max_delay_ms: 3_600_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum JobFailure {
+    Transient,
+    Timeout,
+    #[serde(rename_all = "camelCase")]
+    RateLimited {
+        retry_after_ms: Option<i64>,
+    },
+    Permanent,
+}
+
+/// Derives a stable key from the BSON encoding of the parameters, so the same
+/// request enqueued twice produces the same key.
+pub fn idempotency_key(parameters: &ScoreParameters) -> Result<String, bson::ser::Error> {
+    let document = bson::to_document(parameters)?;
+    let mut bytes = Vec::new();
+    document.to_writer(&mut bytes)?;
+
+    Ok(hex::encode(Sha256::digest(&bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn job() -> ScoreJob {
+        let mut job = ScoreJob::new(ScoreParameters::default()).unwrap();
+        job.start_attempt();
+        job
+    }
+
+    #[test]
+    fn retry_after_is_capped_at_the_maximum_delay() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let failure = JobFailure::RateLimited {
+            retry_after_ms: Some(i64::MAX),
+        };
+
+        assert_eq!(
+            job().next_retry_at(&failure, now),
+            Some(now + Duration::milliseconds(3_600_000))
+        );
+    }
+
+    #[test]
+    fn retry_after_within_the_maximum_delay_is_kept() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let failure = JobFailure::RateLimited {
+            retry_after_ms: Some(120_000),
+        };
+
+        assert_eq!(
+            job().next_retry_at(&failure, now),
+            Some(now + Duration::milliseconds(120_000))
+        );
+    }
+
+    #[test]
+    fn out_of_range_retry_time_is_none() {
+        let mut job = job();
+        job.set_backoff(BackoffPolicy::Fixed {
+            delay_ms: i64::MAX / 1_000,
+        });
+
+        assert_eq!(
+            job.next_retry_at(&JobFailure::Transient, chrono::DateTime::<Utc>::MAX_UTC),
+            None
+        );
+    }
+
+    #[test]
+    fn priority_is_stored_as_its_rank() {
+        let ranks: Vec<bson::Bson> = [JobPriority::Low, JobPriority::Normal, JobPriority::High]
+            .iter()
+            .map(|priority| bson::to_bson(priority).unwrap())
+            .collect();
+
+        assert_eq!(
+            ranks,
+            vec![
+                bson::Bson::Int32(0),
+                bson::Bson::Int32(1),
+                bson::Bson::Int32(2)
+            ]
+        );
+        assert_eq!(
+            bson::from_bson::<JobPriority>(bson::Bson::Int32(2)).unwrap(),
+            JobPriority::High
+        );
+        assert!(bson::from_bson::<JobPriority>(bson::Bson::Int32(3)).is_err());
+    }
+
+    #[test]
+    fn extreme_stored_delays_do_not_panic() {
+        let now = Utc.with_ymd_and_hms(2021, 1, 1, 0, 0, 0).unwrap();
+        let mut job = job();
+
+        job.set_backoff(BackoffPolicy::Fixed { delay_ms: i64::MIN });
+        assert_eq!(job.next_retry_at(&JobFailure::Transient, now), Some(now));
+
+        job.set_backoff(BackoffPolicy::Exponential {
+            initial_delay_ms: i64::MIN,
+            multiplier: f64::NAN,
+            max_delay_ms: i64::MIN,
+        });
+        let failure = JobFailure::RateLimited {
+            retry_after_ms: Some(i64::MIN),
+        };
+        assert_eq!(job.next_retry_at(&failure, now), Some(now));
+    }
+
+    #[test]
+    fn idempotency_key_is_stable() {
+        let parameters = ScoreParameters::default();
+
+        assert_eq!(
+            idempotency_key(&parameters).unwrap(),
+            idempotency_key(&parameters.clone()).unwrap()
+        );
+        assert_eq!(idempotency_key(&parameters).unwrap().len(), 64);
+    }
+}
@@ -1,4 +1,6 @@
-use getset::{Getters, Setters};
+use crate::blobs::BlobRef;
+use crate::budgets::Budget;
+use getset::{Getters, MutGetters, Setters};
 use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize, Serialize, Debug, Getters, Setters, Default)]
@@ -165,8 +167,8 @@ pub struct AuditSimple {
     display_value: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
-#[getset(get = "pub", set = "pub")]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, MutGetters, Default, Clone)]
+#[getset(get = "pub", set = "pub", get_mut = "pub")]
 #[serde(rename_all = "camelCase")]
 pub struct Audit<T> {
     id: String,
@@ -183,8 +185,8 @@ pub struct Audit<T> {
 
 pub type AuditTable<T> = Audit<Table<T>>;
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
-#[getset(get = "pub", set = "pub")]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, MutGetters, Default, Clone)]
+#[getset(get = "pub", set = "pub", get_mut = "pub")]
 pub struct Table<T> {
     headings: Option<Vec<TableHeading>>,
     items: Vec<T>,
@@ -307,19 +309,21 @@ pub struct ThirdPartyEntity {
     url: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
-#[getset(get = "pub", set = "pub")]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, MutGetters, Default, Clone)]
+#[getset(get = "pub", set = "pub", get_mut = "pub")]
 pub struct Filmstrip {
     scale: i64,
     items: Vec<FilmstripItem>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
-#[getset(get = "pub", set = "pub")]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, MutGetters, Default, Clone)]
+#[getset(get = "pub", set = "pub", get_mut = "pub")]
 pub struct FilmstripItem {
     timing: i64,
     timestamp: f64,
     data: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    blob: Option<BlobRef>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
@@ -383,7 +387,7 @@ pub struct ConfigSettings {
     channel: String,
     locale: String,
     only_categories: Vec<String>,
-    //budgets: String, //TODO
+    budgets: Option<Vec<Budget>>,
     //blockedUrlPatterns: String, //TODO
     //additionalTraceCategories: String,
     //extraHeaders
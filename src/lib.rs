@@ -1,6 +1,41 @@
+pub mod alerts;
+pub mod anomalies;
+pub mod assertions;
+pub mod badges;
+pub mod blobs;
+pub mod budgets;
+pub mod charts;
+pub mod collections;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod detail_chunks;
+pub mod detail_diff;
+pub mod fields;
+mod format;
+pub mod html_report;
+pub mod jobs;
 pub mod lh_models;
+pub mod markdown;
+pub mod metrics;
+pub mod migrations;
+pub mod notifications;
+pub mod pagination;
+pub mod queries;
+pub mod regression;
+mod report;
+pub mod repository;
+pub mod rollups;
+pub mod significance;
+pub mod site_updates;
+pub mod slos;
+pub mod webhooks;
+
+#[cfg(test)]
+#[path = "../tests/fixtures/mod.rs"]
+mod fixtures;
 
 use bson::oid::ObjectId;
+use budgets::Budget;
 use getset::{Getters, Setters};
 use lh_models::{
     Audit, AuditSimple, AuditTable, CachePolicyItem, Filmstrip, LatencyItem, NetworkRequest,
@@ -9,7 +44,7 @@ use lh_models::{
 };
 use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default)]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
 #[getset(get = "pub", set = "pub")]
 pub struct ScoreParameters {
     pub page: Option<PageScoreParameters>,
@@ -32,7 +67,7 @@ impl ScoreParameters {
     }
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default)]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
 #[getset(get = "pub", set = "pub")]
 #[serde(rename_all = "camelCase")]
 pub struct PageScoreParameters {
@@ -47,7 +82,7 @@ pub struct PageScoreParameters {
     pub cookie: Option<Cookie>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default)]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
 #[getset(get = "pub", set = "pub")]
 #[serde(rename_all = "camelCase")]
 pub struct SiteScoreParameters {
@@ -56,10 +91,21 @@ pub struct SiteScoreParameters {
     pub cookie: Option<Cookie>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
 #[getset(get = "pub", set = "pub")]
 #[serde(rename_all = "camelCase")]
 pub struct AuditDetail {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    id: Option<ObjectId>,
+    #[serde(default)]
+    schema_version: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    site_id: Option<ObjectId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_profile_id: Option<String>,
     lighthouse_version: String,
     requested_url: String,
     final_url: String,
@@ -90,6 +136,53 @@ pub struct AuditDetail {
     render_blocking_resources: Option<Audit<Opportunity>>,
     uses_long_cache_ttl: Option<AuditTable<CachePolicyItem>>,
     user_timings: Option<AuditTable<UserTiming>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chunk_ids: Option<Vec<ObjectId>>,
+}
+
+/// New details start at the current schema version; stored documents without
+/// a `schemaVersion` are legacy and deserialize as version 0.
+impl Default for AuditDetail {
+    fn default() -> AuditDetail {
+        AuditDetail {
+            id: Default::default(),
+            schema_version: migrations::AUDIT_DETAIL_SCHEMA_VERSION,
+            site_id: Default::default(),
+            page_id: Default::default(),
+            audit_profile_id: Default::default(),
+            lighthouse_version: Default::default(),
+            requested_url: Default::default(),
+            final_url: Default::default(),
+            fetch_time: Default::default(),
+            categories: Default::default(),
+            config_settings: Default::default(),
+            web_vitals: Default::default(),
+            largest_contentful_paint_element: Default::default(),
+            network_requests: Default::default(),
+            resource_summary: Default::default(),
+            third_party_summary: Default::default(),
+            screenshot_thumbnails: Default::default(),
+            uses_responsive_images: Default::default(),
+            uses_optimized_images: Default::default(),
+            uses_webp_images: Default::default(),
+            offscreen_images: Default::default(),
+            uses_http2: Default::default(),
+            bootup_time: Default::default(),
+            main_thread_work_breakdown: Default::default(),
+            uses_rel_preconnect: Default::default(),
+            network_server_latency: Default::default(),
+            network_rtt: Default::default(),
+            main_thread_tasks: Default::default(),
+            unminified_css: Default::default(),
+            unminified_javascript: Default::default(),
+            unused_css_rules: Default::default(),
+            unused_javascript: Default::default(),
+            render_blocking_resources: Default::default(),
+            uses_long_cache_ttl: Default::default(),
+            user_timings: Default::default(),
+            chunk_ids: Default::default(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
@@ -140,13 +233,15 @@ pub struct LighthouseSettings {
     versions: Vec<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
 #[getset(get = "pub", set = "pub")]
 #[serde(rename_all = "camelCase")]
 pub struct Site {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "_id")]
     id: Option<ObjectId>,
+    #[serde(default)]
+    schema_version: i32,
     name: String,
     group_id: Option<ObjectId>,
     pages: Vec<Page>,
@@ -154,6 +249,26 @@ pub struct Site {
     last_run_id: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
     authentication: Option<Authentication>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budgets: Option<Vec<Budget>>,
+}
+
+/// New sites start at the current schema version; stored documents without
+/// a `schemaVersion` are legacy and deserialize as version 0.
+impl Default for Site {
+    fn default() -> Site {
+        Site {
+            id: Default::default(),
+            schema_version: migrations::SITE_SCHEMA_VERSION,
+            name: Default::default(),
+            group_id: Default::default(),
+            pages: Default::default(),
+            audit_profiles: Default::default(),
+            last_run_id: Default::default(),
+            authentication: Default::default(),
+            budgets: Default::default(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
@@ -213,6 +328,8 @@ pub struct AuditProfile {
     enabled: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     blocked_url_patterns: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    budgets: Option<Vec<Budget>>,
 }
 
 impl AuditProfile {
@@ -229,6 +346,7 @@ impl AuditProfile {
             lighthouse_version,
             enabled: None,
             blocked_url_patterns: None,
+            budgets: None,
         }
     }
 }
@@ -240,6 +358,8 @@ pub struct AuditSummary {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "_id")]
     id: Option<ObjectId>,
+    #[serde(default)]
+    schema_version: i32,
     site_id: ObjectId,
     site_run_id: i32,
     page_id: String,
@@ -253,6 +373,7 @@ pub struct AuditSummary {
 }
 
 impl AuditSummary {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         site_id: ObjectId,
         site_run_id: i32,
@@ -267,6 +388,7 @@ impl AuditSummary {
     ) -> AuditSummary {
         AuditSummary {
             id: None,
+            schema_version: migrations::AUDIT_SUMMARY_SCHEMA_VERSION,
             site_id,
             site_run_id,
             page_id,
@@ -281,6 +403,41 @@ impl AuditSummary {
     }
 }
 
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct SiteRun {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    id: Option<ObjectId>,
+    site_id: ObjectId,
+    run_id: i32,
+    status: SiteRunStatus,
+    started_at: bson::DateTime,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed_at: Option<bson::DateTime>,
+}
+
+impl SiteRun {
+    pub fn new(site_id: ObjectId, run_id: i32, started_at: bson::DateTime) -> SiteRun {
+        SiteRun {
+            id: None,
+            site_id,
+            run_id,
+            status: SiteRunStatus::Running,
+            started_at,
+            completed_at: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+pub enum SiteRunStatus {
+    Running,
+    Completed,
+    Failed,
+}
+
 #[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
 #[getset(get = "pub", set = "pub")]
 pub struct Cookie {
@@ -0,0 +1,203 @@
+use crate::format::format_ms;
+use crate::metrics::Rating;
+use crate::regression::RegressionReport;
+use crate::report::{delta_text, headline, ranked_opportunities, rows};
+use crate::{AuditDetail, AuditSummary};
+
+/// A pull-request comment: a table of the score and web vitals with their
+/// change against the baseline, and the largest opportunities of the run in
+/// a collapsed section when the detail is given.
+pub fn pr_comment(
+    summary: &AuditSummary,
+    comparison: Option<&RegressionReport>,
+    detail: Option<&AuditDetail>,
+    top_opportunities: usize,
+) -> String {
+    let mut markdown = format!(
+        "### Lighthouse: {} ({})\n\n",
+        escape(summary.page_id()),
+        escape(summary.audit_profile().name())
+    );
+    if let Some(headline) = headline(comparison) {
+        markdown.push_str(&format!("**{}**\n\n", escape(&headline)));
+    }
+
+    if comparison.is_some() {
+        markdown.push_str("| Metric | Value | Change | Rating |\n|---|---:|---:|---|\n");
+    } else {
+        markdown.push_str("| Metric | Value | Rating |\n|---|---:|---|\n");
+    }
+    for row in rows(summary, comparison) {
+        let rating = format!("{} {}", rating_emoji(row.rating), row.rating.label());
+        if comparison.is_some() {
+            markdown.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                row.metric.label(),
+                row.value,
+                delta_text(&row).trim(),
+                rating
+            ));
+        } else {
+            markdown.push_str(&format!(
+                "| {} | {} | {} |\n",
+                row.metric.label(),
+                row.value,
+                rating
+            ));
+        }
+    }
+
+    let opportunities: Vec<_> = detail
+        .map(ranked_opportunities)
+        .unwrap_or_default()
+        .into_iter()
+        .take(top_opportunities)
+        .collect();
+    if !opportunities.is_empty() {
+        markdown.push_str(&format!(
+            "\n<details>\n<summary>Top {} opportunities</summary>\n\n| Opportunity | Est. savings |\n|---|---:|\n",
+            opportunities.len()
+        ));
+        for (audit, savings) in opportunities {
+            markdown.push_str(&format!(
+                "| {} | {} |\n",
+                escape(audit.title()),
+                format_ms(savings)
+            ));
+        }
+        markdown.push_str("\n</details>\n");
+    }
+
+    let legend = if comparison.is_some() {
+        "▲ better, ▼ worse than the baseline · "
+    } else {
+        ""
+    };
+    markdown.push_str(&format!(
+        "\n<sub>{}run {} · {}</sub>\n",
+        legend,
+        summary.site_run_id(),
+        escape(summary.fetch_time())
+    ));
+    markdown
+}
+
+fn rating_emoji(rating: Rating) -> &'static str {
+    match rating {
+        Rating::Good => "🟢",
+        Rating::NeedsImprovement => "🟠",
+        Rating::Poor => "🔴",
+    }
+}
+
+/// Keeps text inside a table cell and out of HTML.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('|', "\\|")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace(['\r', '\n'], " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::regression::{self, Baseline, BaselineValues, RegressionThresholds};
+
+    fn summary() -> AuditSummary {
+        serde_json::from_str(include_str!("../tests/fixtures/audit_summary.json")).unwrap()
+    }
+
+    fn detail() -> AuditDetail {
+        serde_json::from_str(include_str!("../tests/fixtures/audit_detail.json")).unwrap()
+    }
+
+    /// Against a baseline scoring 93 with a Speed Index of 2.5 s and a
+    /// Largest Contentful Paint of 3.6 s: the run is slower on the first and
+    /// faster on the second, both lower-is-better metrics.
+    fn comparison(summary: &AuditSummary) -> RegressionReport {
+        let mut baseline = summary.clone();
+        let mut categories = baseline.categories().clone();
+        let mut performance = categories.performance().clone();
+        performance.set_score(0.93);
+        categories.set_performance(performance);
+        baseline.set_categories(categories);
+        let mut web_vitals = baseline.web_vitals().clone();
+        let mut speed_index = web_vitals.speed_index().clone();
+        speed_index.set_numeric_value(Some(2480.0));
+        web_vitals.set_speed_index(speed_index);
+        let mut lcp = web_vitals.largest_contentful_paint().clone().unwrap();
+        lcp.set_numeric_value(Some(3600.0));
+        web_vitals.set_largest_contentful_paint(Some(lcp));
+        baseline.set_web_vitals(web_vitals);
+
+        regression::compare(
+            summary,
+            &Baseline::PreviousRun,
+            &BaselineValues::from_summary(&baseline),
+            &RegressionThresholds::default(),
+        )
+    }
+
+    fn row<'a>(markdown: &'a str, label: &str) -> &'a str {
+        markdown
+            .lines()
+            .find(|line| line.starts_with(&format!("| {} |", label)))
+            .unwrap()
+    }
+
+    #[test]
+    fn pr_comment_matches_snapshot() {
+        let summary = summary();
+        let markdown = pr_comment(&summary, Some(&comparison(&summary)), Some(&detail()), 5);
+
+        assert_eq!(
+            markdown,
+            include_str!("../tests/fixtures/markdown/pr_comment.md")
+        );
+    }
+
+    #[test]
+    fn arrows_follow_the_metric_direction() {
+        let summary = summary();
+        let markdown = pr_comment(&summary, Some(&comparison(&summary)), None, 5);
+
+        assert!(row(&markdown, "Performance").contains("| ▼ -7 |"));
+        assert!(row(&markdown, "Speed Index").contains("| ▼ +0.6 s |"));
+        assert!(row(&markdown, "Largest Contentful Paint").contains("| ▲ -0.7 s |"));
+    }
+
+    #[test]
+    fn pr_comment_without_comparison_or_detail() {
+        let markdown = pr_comment(&summary(), None, None, 5);
+
+        assert_eq!(
+            markdown,
+            include_str!("../tests/fixtures/markdown/pr_comment_plain.md")
+        );
+        assert!(!markdown.contains('▲') && !markdown.contains('▼'));
+        assert!(!markdown.contains("<details>"));
+    }
+
+    #[test]
+    fn pr_comment_limits_opportunities() {
+        let markdown = pr_comment(&summary(), None, Some(&detail()), 1);
+
+        assert!(markdown.contains("<summary>Top 1 opportunities</summary>"));
+        assert!(markdown.contains("| Remove unused JavaScript | 450 ms |"));
+        assert!(!markdown.contains("render-blocking"));
+    }
+
+    #[test]
+    fn pr_comment_escapes_table_and_html_text() {
+        let mut summary = summary();
+        summary.set_page_id("a|b\n<img src=x>".to_owned());
+
+        let markdown = pr_comment(&summary, None, None, 5);
+
+        assert!(markdown.starts_with(
+            "### Lighthouse: a\\|b &lt;img src=x&gt; (Mobile &lt;Moto G4&gt; & 4G)\n"
+        ));
+        assert!(!markdown.contains("<img"));
+    }
+}
@@ -0,0 +1,284 @@
+use crate::fields::field;
+use crate::lh_models::{AuditSimple, Report};
+use crate::queries::AuditSummaryField;
+use crate::{AuditSummary, WebVitals};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The performance score and the metrics kept in `WebVitals`.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Metric {
+    Score,
+    FirstContentfulPaint,
+    SpeedIndex,
+    LargestContentfulPaint,
+    Interactive,
+    TotalBlockingTime,
+    CumulativeLayoutShift,
+    MaxPotentialFid,
+    FirstMeaningfulPaint,
+    FirstCpuIdle,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Rating {
+    Good,
+    NeedsImprovement,
+    Poor,
+}
+
+impl Metric {
+    pub fn all() -> [Metric; 10] {
+        [
+            Metric::Score,
+            Metric::FirstContentfulPaint,
+            Metric::SpeedIndex,
+            Metric::LargestContentfulPaint,
+            Metric::Interactive,
+            Metric::TotalBlockingTime,
+            Metric::CumulativeLayoutShift,
+            Metric::MaxPotentialFid,
+            Metric::FirstMeaningfulPaint,
+            Metric::FirstCpuIdle,
+        ]
+    }
+
+    /// The metrics reported by current Lighthouse versions.
+    pub fn web_vitals() -> [Metric; 6] {
+        [
+            Metric::FirstContentfulPaint,
+            Metric::SpeedIndex,
+            Metric::LargestContentfulPaint,
+            Metric::Interactive,
+            Metric::TotalBlockingTime,
+            Metric::CumulativeLayoutShift,
+        ]
+    }
+
+    /// The Lighthouse audit id, or `performance` for the score.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Metric::Score => "performance",
+            Metric::FirstContentfulPaint => "first-contentful-paint",
+            Metric::SpeedIndex => "speed-index",
+            Metric::LargestContentfulPaint => "largest-contentful-paint",
+            Metric::Interactive => "interactive",
+            Metric::TotalBlockingTime => "total-blocking-time",
+            Metric::CumulativeLayoutShift => "cumulative-layout-shift",
+            Metric::MaxPotentialFid => "max-potential-fid",
+            Metric::FirstMeaningfulPaint => "first-meaningful-paint",
+            Metric::FirstCpuIdle => "first-cpu-idle",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Metric> {
+        Metric::all().iter().copied().find(|m| m.id() == id)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Metric::Score => "Performance",
+            Metric::FirstContentfulPaint => "First Contentful Paint",
+            Metric::SpeedIndex => "Speed Index",
+            Metric::LargestContentfulPaint => "Largest Contentful Paint",
+            Metric::Interactive => "Time to Interactive",
+            Metric::TotalBlockingTime => "Total Blocking Time",
+            Metric::CumulativeLayoutShift => "Cumulative Layout Shift",
+            Metric::MaxPotentialFid => "Max Potential First Input Delay",
+            Metric::FirstMeaningfulPaint => "First Meaningful Paint",
+            Metric::FirstCpuIdle => "First CPU Idle",
+        }
+    }
+
+    pub fn abbreviation(&self) -> &'static str {
+        match self {
+            Metric::Score => "Score",
+            Metric::FirstContentfulPaint => "FCP",
+            Metric::SpeedIndex => "SI",
+            Metric::LargestContentfulPaint => "LCP",
+            Metric::Interactive => "TTI",
+            Metric::TotalBlockingTime => "TBT",
+            Metric::CumulativeLayoutShift => "CLS",
+            Metric::MaxPotentialFid => "Max FID",
+            Metric::FirstMeaningfulPaint => "FMP",
+            Metric::FirstCpuIdle => "FCI",
+        }
+    }
+
+    /// Only the score improves as it grows; the others are timings or, for
+    /// CLS, a unitless shift.
+    pub fn higher_is_better(&self) -> bool {
+        *self == Metric::Score
+    }
+
+    pub fn is_timing(&self) -> bool {
+        !matches!(self, Metric::Score | Metric::CumulativeLayoutShift)
+    }
+
+    /// Good and poor boundaries, using Lighthouse's mobile scoring curves.
+    /// Scores are in 0..=1.
+    pub fn thresholds(&self) -> (f64, f64) {
+        match self {
+            Metric::Score => (0.9, 0.5),
+            Metric::FirstContentfulPaint => (1800.0, 3000.0),
+            Metric::SpeedIndex => (3400.0, 5800.0),
+            Metric::LargestContentfulPaint => (2500.0, 4000.0),
+            Metric::Interactive => (3800.0, 7300.0),
+            Metric::TotalBlockingTime => (200.0, 600.0),
+            Metric::CumulativeLayoutShift => (0.1, 0.25),
+            Metric::MaxPotentialFid => (130.0, 250.0),
+            Metric::FirstMeaningfulPaint => (2000.0, 4000.0),
+            Metric::FirstCpuIdle => (3800.0, 7300.0),
+        }
+    }
+
+    pub fn rating(&self, value: f64) -> Rating {
+        let (good, poor) = self.thresholds();
+        if self.higher_is_better() {
+            if value >= good {
+                Rating::Good
+            } else if value >= poor {
+                Rating::NeedsImprovement
+            } else {
+                Rating::Poor
+            }
+        } else if value <= good {
+            Rating::Good
+        } else if value <= poor {
+            Rating::NeedsImprovement
+        } else {
+            Rating::Poor
+        }
+    }
+
+    /// Dotted path of the value in an `AuditSummary` document.
+    pub fn path(&self) -> String {
+        let web_vital = match self {
+            Metric::Score => return AuditSummaryField::Score.path(),
+            Metric::FirstContentfulPaint => "first_contentful_paint",
+            Metric::SpeedIndex => "speed_index",
+            Metric::LargestContentfulPaint => "largest_contentful_paint",
+            Metric::Interactive => "interactive",
+            Metric::TotalBlockingTime => "total_blocking_time",
+            Metric::CumulativeLayoutShift => "cumulative_layout_shift",
+            Metric::MaxPotentialFid => "max_potential_fid",
+            Metric::FirstMeaningfulPaint => "first_meaningful_paint",
+            Metric::FirstCpuIdle => "first_cpu_idle",
+        };
+
+        format!(
+            "{}.{}.{}",
+            AuditSummaryField::WebVitals.path(),
+            field::<WebVitals>(web_vital),
+            field::<AuditSimple>("numeric_value")
+        )
+    }
+
+    pub fn value(&self, summary: &AuditSummary) -> Option<f64> {
+        match self {
+            Metric::Score => Some(*summary.categories().performance().score()),
+            _ => self.web_vital_value(summary.web_vitals()),
+        }
+    }
+
+    /// The value in a raw Lighthouse report.
+    pub fn report_value(&self, report: &Report) -> Option<f64> {
+        let audits = report.audits();
+        let audit: Option<&AuditSimple> = match self {
+            Metric::Score => return Some(*report.categories().performance().score()),
+            Metric::FirstContentfulPaint => Some(audits.first_contentful_paint()),
+            Metric::SpeedIndex => Some(audits.speed_index()),
+            Metric::LargestContentfulPaint => audits.largest_contentful_paint().as_ref(),
+            Metric::Interactive => Some(audits.interactive()),
+            Metric::TotalBlockingTime => Some(audits.total_blocking_time()),
+            Metric::CumulativeLayoutShift => audits.cumulative_layout_shift().as_ref(),
+            Metric::MaxPotentialFid => Some(audits.max_potential_fid()),
+            Metric::FirstMeaningfulPaint => Some(audits.first_meaningful_paint()),
+            Metric::FirstCpuIdle => Some(audits.first_cpu_idle()),
+        };
+
+        audit.and_then(|audit| *audit.numeric_value())
+    }
+
+    pub fn web_vital_value(&self, web_vitals: &WebVitals) -> Option<f64> {
+        let audit: Option<&AuditSimple> = match self {
+            Metric::Score => None,
+            Metric::FirstContentfulPaint => Some(web_vitals.first_contentful_paint()),
+            Metric::SpeedIndex => Some(web_vitals.speed_index()),
+            Metric::LargestContentfulPaint => web_vitals.largest_contentful_paint().as_ref(),
+            Metric::Interactive => Some(web_vitals.interactive()),
+            Metric::TotalBlockingTime => Some(web_vitals.total_blocking_time()),
+            Metric::CumulativeLayoutShift => web_vitals.cumulative_layout_shift().as_ref(),
+            Metric::MaxPotentialFid => Some(web_vitals.max_potential_fid()),
+            Metric::FirstMeaningfulPaint => Some(web_vitals.first_meaningful_paint()),
+            Metric::FirstCpuIdle => Some(web_vitals.first_cpu_idle()),
+        };
+
+        audit.and_then(|audit| *audit.numeric_value())
+    }
+
+    /// Formats a value the way Lighthouse displays it.
+    pub fn format(&self, value: f64) -> String {
+        match self {
+            Metric::Score => format!("{:.0}", value * 100.0),
+            Metric::CumulativeLayoutShift => format!("{:.3}", value),
+            Metric::TotalBlockingTime | Metric::MaxPotentialFid => format!("{:.0} ms", value),
+            _ => format!("{:.1} s", value / 1000.0),
+        }
+    }
+
+    /// Formats a change with its sign, e.g. `+0.4 s` or `-3`.
+    pub fn format_delta(&self, delta: f64) -> String {
+        let sign = if delta < 0.0 { "-" } else { "+" };
+        format!("{}{}", sign, self.format(delta.abs()))
+    }
+}
+
+impl Rating {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Rating::Good => "Good",
+            Rating::NeedsImprovement => "Needs improvement",
+            Rating::Poor => "Poor",
+        }
+    }
+
+    /// Lighthouse's colour for the band.
+    pub fn color(&self) -> &'static str {
+        match self {
+            Rating::Good => "#0c6",
+            Rating::NeedsImprovement => "#fa3",
+            Rating::Poor => "#f33",
+        }
+    }
+}
+
+/// All metric values present in a summary.
+pub fn metric_values(summary: &AuditSummary) -> BTreeMap<Metric, f64> {
+    Metric::all()
+        .iter()
+        .filter_map(|metric| metric.value(summary).map(|value| (*metric, value)))
+        .collect()
+}
+
+pub fn median(values: &[f64]) -> Option<f64> {
+    percentile(values, 50.0)
+}
+
+/// Linearly interpolated percentile (0..=100) of the values.
+pub fn percentile(values: &[f64], percentile: f64) -> Option<f64> {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if sorted.is_empty() {
+        return None;
+    }
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let rank = (percentile.clamp(0.0, 100.0) / 100.0) * (sorted.len() - 1) as f64;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let weight = rank - lower as f64;
+
+    Some(sorted[lower] + (sorted[upper] - sorted[lower]) * weight)
+}
@@ -0,0 +1,559 @@
+use crate::repository::Record;
+use bson::{doc, Bson, Document};
+use getset::Getters;
+use std::convert::TryFrom;
+use std::fmt;
+
+pub const SITE_SCHEMA_VERSION: i32 = 1;
+pub const AUDIT_SUMMARY_SCHEMA_VERSION: i32 = 1;
+pub const AUDIT_DETAIL_SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA_VERSION: &str = "schemaVersion";
+
+/// Upgrades a raw document from `from_version` to `from_version + 1`.
+/// Upgrades must tolerate documents that already have the newer shape.
+#[derive(Getters, Clone)]
+#[getset(get = "pub")]
+pub struct Migration {
+    from_version: i32,
+    description: &'static str,
+    upgrade: fn(&mut Document) -> Result<(), MigrationError>,
+}
+
+impl Migration {
+    pub fn new(
+        from_version: i32,
+        description: &'static str,
+        upgrade: fn(&mut Document) -> Result<(), MigrationError>,
+    ) -> Migration {
+        Migration {
+            from_version,
+            description,
+            upgrade,
+        }
+    }
+}
+
+impl fmt::Debug for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migration")
+            .field("from_version", &self.from_version)
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    FutureVersion {
+        found: i32,
+        supported: i32,
+    },
+    MissingMigration(i32),
+    InvalidDocument(String),
+    Deserialization(bson::de::Error),
+    #[cfg(feature = "mongodb")]
+    Database(mongodb::error::Error),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::FutureVersion { found, supported } => write!(
+                f,
+                "schema version {} is newer than the supported version {}",
+                found, supported
+            ),
+            MigrationError::MissingMigration(version) => {
+                write!(f, "no migration from schema version {}", version)
+            }
+            MigrationError::InvalidDocument(message) => write!(f, "invalid document: {}", message),
+            MigrationError::Deserialization(e) => write!(f, "failed to deserialize record: {}", e),
+            #[cfg(feature = "mongodb")]
+            MigrationError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<bson::de::Error> for MigrationError {
+    fn from(e: bson::de::Error) -> MigrationError {
+        MigrationError::Deserialization(e)
+    }
+}
+
+#[cfg(feature = "mongodb")]
+impl From<mongodb::error::Error> for MigrationError {
+    fn from(e: mongodb::error::Error) -> MigrationError {
+        MigrationError::Database(e)
+    }
+}
+
+/// Documents written before versioning have no `schemaVersion` and count as 0.
+/// Versions written through a JavaScript client arrive as integral doubles.
+pub fn schema_version(document: &Document) -> Result<i32, MigrationError> {
+    let invalid = |version: &dyn fmt::Display| {
+        MigrationError::InvalidDocument(format!("schemaVersion {} is not a valid version", version))
+    };
+
+    match document.get(SCHEMA_VERSION) {
+        None => Ok(0),
+        Some(Bson::Int32(version)) => Ok(*version),
+        Some(Bson::Int64(version)) => i32::try_from(*version).map_err(|_| invalid(version)),
+        Some(Bson::Double(version))
+            if version.fract() == 0.0
+                && *version >= f64::from(i32::MIN)
+                && *version <= f64::from(i32::MAX) =>
+        {
+            Ok(*version as i32)
+        }
+        Some(other) => Err(invalid(other)),
+    }
+}
+
+/// Applies the migrations needed to bring the document to `current_version`.
+/// Returns whether the document changed.
+pub fn upgrade(
+    document: &mut Document,
+    current_version: i32,
+    migrations: &[Migration],
+) -> Result<bool, MigrationError> {
+    let mut version = schema_version(document)?;
+    if version > current_version {
+        return Err(MigrationError::FutureVersion {
+            found: version,
+            supported: current_version,
+        });
+    }
+    if version == current_version {
+        return Ok(false);
+    }
+
+    while version < current_version {
+        let migration = migrations
+            .iter()
+            .find(|m| m.from_version == version)
+            .ok_or(MigrationError::MissingMigration(version))?;
+        (migration.upgrade)(document)?;
+        version += 1;
+    }
+    document.insert(SCHEMA_VERSION, current_version);
+
+    Ok(true)
+}
+
+/// Upgrades a stored document and deserializes it.
+pub fn read<T: Record>(mut document: Document) -> Result<T, MigrationError> {
+    upgrade(&mut document, T::SCHEMA_VERSION, &T::migrations())?;
+    Ok(bson::from_document(document)?)
+}
+
+/// Upgrades a batch of stored documents, returning only those that changed
+/// and need to be written back.
+pub fn upgrade_batch<T: Record>(
+    documents: impl IntoIterator<Item = Document>,
+) -> Result<Vec<Document>, MigrationError> {
+    let migrations = T::migrations();
+    let mut upgraded = Vec::new();
+    for mut document in documents {
+        if upgrade(&mut document, T::SCHEMA_VERSION, &migrations)? {
+            upgraded.push(document);
+        }
+    }
+
+    Ok(upgraded)
+}
+
+/// Matches documents below the current schema version, including those
+/// without a `schemaVersion`.
+pub fn outdated_filter<T: Record>() -> Document {
+    doc! { SCHEMA_VERSION: { "$not": { "$gte": T::SCHEMA_VERSION } } }
+}
+
+/// Rewrites every outdated document in the record's collection, returning
+/// the number of documents upgraded.
+#[cfg(feature = "mongodb")]
+pub fn migrate_collection<T: Record>(
+    database: &mongodb::sync::Database,
+) -> Result<usize, MigrationError> {
+    let collection = database.collection(T::COLLECTION);
+    let migrations = T::migrations();
+    let mut count = 0;
+
+    for document in collection.find(outdated_filter::<T>(), None)? {
+        let mut document = document?;
+        if upgrade(&mut document, T::SCHEMA_VERSION, &migrations)? {
+            let id = document
+                .get("_id")
+                .cloned()
+                .ok_or_else(|| MigrationError::InvalidDocument("missing _id".to_owned()))?;
+            collection.replace_one(doc! { "_id": id }, document, None)?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+pub fn site_migrations() -> Vec<Migration> {
+    vec![Migration::new(
+        0,
+        "default run id and page/profile lists",
+        site_v0_to_v1,
+    )]
+}
+
+pub fn audit_summary_migrations() -> Vec<Migration> {
+    vec![Migration::new(
+        0,
+        "normalize web vitals to audit objects",
+        web_vitals_v0_to_v1,
+    )]
+}
+
+pub fn audit_detail_migrations() -> Vec<Migration> {
+    vec![Migration::new(
+        0,
+        "normalize web vitals to audit objects",
+        web_vitals_v0_to_v1,
+    )]
+}
+
+fn site_v0_to_v1(document: &mut Document) -> Result<(), MigrationError> {
+    if !document.contains_key("lastRunId") {
+        document.insert("lastRunId", 0);
+    }
+    for field in &["pages", "auditProfiles"] {
+        if !document.contains_key(field) {
+            document.insert(*field, Bson::Array(Vec::new()));
+        }
+    }
+
+    Ok(())
+}
+
+const WEB_VITALS: &[&str] = &[
+    "firstContentfulPaint",
+    "speedIndex",
+    "largestContentfulPaint",
+    "interactive",
+    "totalBlockingTime",
+    "cumulativeLayoutShift",
+    "maxPotentialFid",
+    "firstMeaningfulPaint",
+    "firstCpuIdle",
+];
+
+/// Vitals that `WebVitals` requires; LCP and CLS are optional.
+const REQUIRED_WEB_VITALS: &[&str] = &[
+    "firstContentfulPaint",
+    "speedIndex",
+    "interactive",
+    "totalBlockingTime",
+    "maxPotentialFid",
+    "firstMeaningfulPaint",
+    "firstCpuIdle",
+];
+
+/// Brings each web vital to the `AuditSimple` shape. Before versioning, a
+/// vital was stored as a bare number, as a Lighthouse 4 audit (`rawValue`
+/// and a formatted `displayValue` array), or not at all when the Lighthouse
+/// version did not report it (TBT before 5, the LH5 metrics from 6 on).
+fn web_vitals_v0_to_v1(document: &mut Document) -> Result<(), MigrationError> {
+    let web_vitals = match document.get_mut("webVitals") {
+        Some(Bson::Document(web_vitals)) => web_vitals,
+        Some(_) => {
+            return Err(MigrationError::InvalidDocument(
+                "webVitals is not a document".to_owned(),
+            ))
+        }
+        None => return Ok(()),
+    };
+
+    for key in WEB_VITALS {
+        let audit = match web_vitals.get(key) {
+            Some(Bson::Document(audit)) => legacy_audit(key, audit.clone()),
+            Some(value) => match as_number(value) {
+                Some(numeric_value) => numeric_audit(key, numeric_value),
+                None if *value == Bson::Null => continue,
+                None => {
+                    return Err(MigrationError::InvalidDocument(format!(
+                        "webVitals.{} is not an audit",
+                        key
+                    )))
+                }
+            },
+            None => continue,
+        };
+        web_vitals.insert(*key, audit);
+    }
+
+    for key in REQUIRED_WEB_VITALS {
+        if matches!(web_vitals.get(key), None | Some(Bson::Null)) {
+            web_vitals.insert(*key, placeholder_audit(key));
+        }
+    }
+
+    Ok(())
+}
+
+fn placeholder_audit(key: &str) -> Document {
+    doc! {
+        "id": audit_id(key),
+        "title": "",
+        "description": "",
+    }
+}
+
+fn numeric_audit(key: &str, numeric_value: f64) -> Document {
+    let mut audit = placeholder_audit(key);
+    audit.insert("numericValue", numeric_value);
+    audit.insert("numericUnit", numeric_unit(key));
+    audit
+}
+
+/// Fills in the identifying fields and renames `rawValue`; audits already in
+/// the current shape pass through unchanged.
+fn legacy_audit(key: &str, mut audit: Document) -> Document {
+    for (field, value) in placeholder_audit(key) {
+        if !audit.contains_key(&field) {
+            audit.insert(field, value);
+        }
+    }
+    if let Some(raw_value) = audit.remove("rawValue") {
+        if let (false, Some(numeric_value)) =
+            (audit.contains_key("numericValue"), as_number(&raw_value))
+        {
+            audit.insert("numericValue", numeric_value);
+            audit.insert("numericUnit", numeric_unit(key));
+        }
+    }
+    if let Some(Bson::Array(parts)) = audit.get("displayValue") {
+        let display_value = format_display_value(parts);
+        audit.insert("displayValue", display_value);
+    }
+    audit
+}
+
+/// Lighthouse 4 wrote `displayValue` as a printf-style template followed by
+/// its arguments, e.g. `["%10d\u{a0}ms", 1712]`.
+fn format_display_value(parts: &[Bson]) -> String {
+    let mut parts = parts.iter();
+    let mut template = match parts.next() {
+        Some(Bson::String(template)) => template.clone(),
+        _ => return String::new(),
+    };
+    for argument in parts {
+        let argument = match argument {
+            Bson::String(s) => s.clone(),
+            other => match as_number(other) {
+                Some(v) => v.to_string(),
+                None => continue,
+            },
+        };
+        if let Some(start) = template.find('%') {
+            let end = template[start + 1..]
+                .find(|c: char| c.is_ascii_alphabetic())
+                .map_or(template.len(), |i| start + 1 + i + 1);
+            template.replace_range(start..end, &argument);
+        }
+    }
+    template
+}
+
+fn as_number(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(v) => Some(*v),
+        Bson::Int32(v) => Some(f64::from(*v)),
+        Bson::Int64(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+fn numeric_unit(key: &str) -> &'static str {
+    if key == "cumulativeLayoutShift" {
+        "unitless"
+    } else {
+        "millisecond"
+    }
+}
+
+/// `firstCpuIdle` -> `first-cpu-idle`
+fn audit_id(key: &str) -> String {
+    let mut id = String::new();
+    for c in key.chars() {
+        if c.is_uppercase() {
+            id.push('-');
+            id.extend(c.to_lowercase());
+        } else {
+            id.push(c);
+        }
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AuditDetail, AuditSummary, Site};
+
+    fn fixture(json: &str) -> Document {
+        match serde_json::from_str::<Bson>(json).unwrap() {
+            Bson::Document(document) => document,
+            other => panic!("fixture is not a document: {:?}", other),
+        }
+    }
+
+    /// Upgrades a legacy document, reads it, and checks the re-serialized
+    /// record is current and not upgraded again.
+    fn round_trip<T: Record>(json: &str) -> T {
+        let mut document = fixture(json);
+        assert_eq!(schema_version(&document).unwrap(), 0);
+        assert!(upgrade(&mut document, T::SCHEMA_VERSION, &T::migrations()).unwrap());
+        assert_eq!(schema_version(&document).unwrap(), T::SCHEMA_VERSION);
+
+        let record: T = read(fixture(json)).unwrap();
+        let mut written = bson::to_document(&record).unwrap();
+        assert_eq!(schema_version(&written).unwrap(), T::SCHEMA_VERSION);
+        assert!(!upgrade(&mut written, T::SCHEMA_VERSION, &T::migrations()).unwrap());
+        record
+    }
+
+    #[test]
+    fn site_v0_gets_run_id_and_lists() {
+        let site: Site = round_trip(include_str!("../tests/fixtures/migrations/site_v0.json"));
+
+        assert_eq!(*site.last_run_id(), 0);
+        assert!(site.pages().is_empty());
+        assert!(site.audit_profiles().is_empty());
+    }
+
+    #[test]
+    fn audit_summary_v0_gets_lh5_metrics() {
+        let summary: AuditSummary = round_trip(include_str!(
+            "../tests/fixtures/migrations/audit_summary_v0.json"
+        ));
+        let web_vitals = summary.web_vitals();
+
+        assert_eq!(web_vitals.first_cpu_idle().id(), "first-cpu-idle");
+        assert_eq!(*web_vitals.max_potential_fid().numeric_value(), None);
+        assert_eq!(
+            *web_vitals.first_contentful_paint().numeric_value(),
+            Some(1712.4)
+        );
+    }
+
+    #[test]
+    fn audit_detail_v0_gets_lh5_metrics() {
+        let detail: AuditDetail = round_trip(include_str!(
+            "../tests/fixtures/migrations/audit_detail_v0.json"
+        ));
+
+        assert_eq!(
+            detail.web_vitals().first_meaningful_paint().id(),
+            "first-meaningful-paint"
+        );
+    }
+
+    #[test]
+    fn audit_summary_v0_lh4_audits_get_numeric_values() {
+        let summary: AuditSummary = round_trip(include_str!(
+            "../tests/fixtures/migrations/audit_summary_v0_lh4.json"
+        ));
+        let web_vitals = summary.web_vitals();
+        let first_contentful_paint = web_vitals.first_contentful_paint();
+
+        assert_eq!(*first_contentful_paint.numeric_value(), Some(2405.6));
+        assert_eq!(
+            first_contentful_paint.numeric_unit().as_deref(),
+            Some("millisecond")
+        );
+        assert_eq!(
+            first_contentful_paint.display_value().as_deref(),
+            Some("2406\u{a0}ms")
+        );
+        assert_eq!(*web_vitals.max_potential_fid().numeric_value(), Some(348.0));
+        assert_eq!(web_vitals.total_blocking_time().id(), "total-blocking-time");
+        assert_eq!(*web_vitals.total_blocking_time().numeric_value(), None);
+        assert!(web_vitals.largest_contentful_paint().is_none());
+    }
+
+    #[test]
+    fn audit_detail_v0_bare_numbers_become_audits() {
+        let detail: AuditDetail = round_trip(include_str!(
+            "../tests/fixtures/migrations/audit_detail_v0_numbers.json"
+        ));
+        let web_vitals = detail.web_vitals();
+
+        assert_eq!(web_vitals.speed_index().id(), "speed-index");
+        assert_eq!(*web_vitals.speed_index().numeric_value(), Some(3512.0));
+        assert_eq!(
+            *web_vitals.first_contentful_paint().numeric_value(),
+            Some(1904.7)
+        );
+        assert_eq!(*web_vitals.first_cpu_idle().numeric_value(), Some(5480.1));
+        assert!(web_vitals.largest_contentful_paint().is_none());
+        assert!(web_vitals.cumulative_layout_shift().is_none());
+    }
+
+    #[test]
+    fn web_vital_of_unknown_shape_is_an_error() {
+        let mut document = doc! { "webVitals": { "speedIndex": "fast" } };
+
+        assert!(matches!(
+            web_vitals_v0_to_v1(&mut document),
+            Err(MigrationError::InvalidDocument(_))
+        ));
+    }
+
+    #[test]
+    fn new_records_start_at_the_current_version() {
+        let site = bson::to_document(&Site::default()).unwrap();
+        let detail = bson::to_document(&AuditDetail::default()).unwrap();
+
+        assert_eq!(schema_version(&site).unwrap(), SITE_SCHEMA_VERSION);
+        assert_eq!(
+            schema_version(&detail).unwrap(),
+            AUDIT_DETAIL_SCHEMA_VERSION
+        );
+    }
+
+    #[test]
+    fn out_of_range_version_is_an_error() {
+        let document = doc! { SCHEMA_VERSION: i64::from(i32::MAX) + 1 };
+
+        assert!(matches!(
+            schema_version(&document),
+            Err(MigrationError::InvalidDocument(_))
+        ));
+    }
+
+    #[test]
+    fn integral_double_version_is_accepted() {
+        assert_eq!(schema_version(&doc! { SCHEMA_VERSION: 1.0 }).unwrap(), 1);
+        assert_eq!(schema_version(&doc! {}).unwrap(), 0);
+    }
+
+    #[test]
+    fn non_integral_or_non_numeric_version_is_an_error() {
+        for version in &[Bson::Double(1.5), Bson::Double(f64::NAN), Bson::from("1")] {
+            let document = doc! { SCHEMA_VERSION: version.clone() };
+
+            assert!(matches!(
+                schema_version(&document),
+                Err(MigrationError::InvalidDocument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn future_version_is_an_error() {
+        let mut document = doc! { SCHEMA_VERSION: SITE_SCHEMA_VERSION + 1 };
+
+        assert!(matches!(
+            upgrade(&mut document, SITE_SCHEMA_VERSION, &site_migrations()),
+            Err(MigrationError::FutureVersion { .. })
+        ));
+    }
+}
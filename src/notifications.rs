@@ -0,0 +1,302 @@
+use crate::format::escape_html;
+use crate::metrics::{Metric, Rating};
+use crate::regression::{RegressionReport, Verdict};
+use crate::report::{delta_text, headline, rows};
+use crate::AuditSummary;
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+/// A rendered email; `text` is the plain-text alternative of `html`.
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct Email {
+    subject: String,
+    text: String,
+    html: String,
+}
+
+fn title(summary: &AuditSummary) -> String {
+    format!(
+        "Performance {} for {}",
+        Metric::Score.format(*summary.categories().performance().score()),
+        summary.page_id()
+    )
+}
+
+fn context(summary: &AuditSummary) -> String {
+    format!(
+        "{} ({}) · run {} · {}",
+        summary.audit_profile().name(),
+        summary.audit_profile().device(),
+        summary.site_run_id(),
+        summary.fetch_time()
+    )
+}
+
+fn rating_emoji(rating: Rating) -> &'static str {
+    match rating {
+        Rating::Good => ":large_green_circle:",
+        Rating::NeedsImprovement => ":large_orange_circle:",
+        Rating::Poor => ":red_circle:",
+    }
+}
+
+/// Slack Block Kit message, with `text` as the notification fallback.
+pub fn slack_message(summary: &AuditSummary, comparison: Option<&RegressionReport>) -> Value {
+    let title = title(summary);
+    let fields: Vec<Value> = rows(summary, comparison)
+        .iter()
+        .map(|row| {
+            json!({
+                "type": "mrkdwn",
+                "text": format!(
+                    "*{}*\n{} {}{}",
+                    escape_mrkdwn(row.metric.label()),
+                    rating_emoji(row.rating),
+                    escape_mrkdwn(&row.value),
+                    escape_mrkdwn(&delta_text(row))
+                ),
+            })
+        })
+        .collect();
+
+    let mut blocks = vec![json!({
+        "type": "header",
+        "text": { "type": "plain_text", "text": title },
+    })];
+    if let Some(headline) = headline(comparison) {
+        blocks.push(json!({
+            "type": "section",
+            "text": { "type": "mrkdwn", "text": escape_mrkdwn(&headline) },
+        }));
+    }
+    blocks.push(json!({ "type": "section", "fields": fields }));
+    blocks.push(json!({
+        "type": "context",
+        "elements": [{ "type": "mrkdwn", "text": escape_mrkdwn(&context(summary)) }],
+    }));
+
+    json!({ "text": escape_mrkdwn(&title), "blocks": blocks })
+}
+
+/// Teams message carrying an Adaptive Card 1.4 attachment.
+pub fn teams_message(summary: &AuditSummary, comparison: Option<&RegressionReport>) -> Value {
+    let facts: Vec<Value> = rows(summary, comparison)
+        .iter()
+        .map(|row| {
+            json!({
+                "title": row.metric.label(),
+                "value": format!("{} ({}){}", row.value, row.rating.label(), delta_text(row)),
+            })
+        })
+        .collect();
+
+    let mut body = vec![json!({
+        "type": "TextBlock",
+        "size": "Large",
+        "weight": "Bolder",
+        "wrap": true,
+        "text": title(summary),
+    })];
+    if let Some(headline) = headline(comparison) {
+        let color = match comparison.map(|report| *report.verdict()) {
+            Some(Verdict::Regressed) => "Attention",
+            Some(Verdict::Improved) => "Good",
+            _ => "Default",
+        };
+        body.push(json!({
+            "type": "TextBlock",
+            "wrap": true,
+            "color": color,
+            "text": headline,
+        }));
+    }
+    body.push(json!({ "type": "FactSet", "facts": facts }));
+    body.push(json!({
+        "type": "TextBlock",
+        "isSubtle": true,
+        "wrap": true,
+        "text": context(summary),
+    }));
+
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "body": body,
+            },
+        }],
+    })
+}
+
+pub fn email(summary: &AuditSummary, comparison: Option<&RegressionReport>) -> Email {
+    let title = title(summary);
+    let headline = headline(comparison);
+    let context = context(summary);
+    let rows = rows(summary, comparison);
+
+    let mut text = format!("{}\n\n", title);
+    if let Some(headline) = &headline {
+        text.push_str(&format!("{}\n\n", headline));
+    }
+    for row in &rows {
+        text.push_str(&format!(
+            "{}: {} ({}){}\n",
+            row.metric.label(),
+            row.value,
+            row.rating.label(),
+            delta_text(row)
+        ));
+    }
+    text.push_str(&format!("\n{}\n", context));
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<body style=\"font-family: sans-serif\">\n<h1>{}</h1>\n",
+        escape_html(&title)
+    );
+    if let Some(headline) = &headline {
+        html.push_str(&format!(
+            "<p><strong>{}</strong></p>\n",
+            escape_html(headline)
+        ));
+    }
+    html.push_str("<table cellpadding=\"4\">\n");
+    for row in &rows {
+        html.push_str(&format!(
+            "<tr><th align=\"left\">{}</th><td style=\"color: {}\">{}</td><td>{}</td></tr>\n",
+            escape_html(row.metric.label()),
+            row.rating.color(),
+            escape_html(&row.value),
+            escape_html(delta_text(row).trim())
+        ));
+    }
+    html.push_str(&format!(
+        "</table>\n<p style=\"color: #757575\">{}</p>\n</body>\n</html>\n",
+        escape_html(&context)
+    ));
+
+    Email {
+        subject: match headline {
+            Some(headline) => format!("{}: {}", title, headline),
+            None => title,
+        },
+        text,
+        html,
+    }
+}
+
+/// Slack treats `<` and `>` as link and mention delimiters and `&` as the
+/// start of an entity in `mrkdwn` and fallback text.
+fn escape_mrkdwn(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{audit_summary, set_metric};
+    use crate::regression::{self, Baseline, BaselineValues, RegressionThresholds};
+
+    /// A slower run than its baseline: the score dropped from 93 and the
+    /// Speed Index rose from 2.5 s.
+    fn comparison(summary: &AuditSummary) -> RegressionReport {
+        let mut baseline = summary.clone();
+        set_metric(&mut baseline, Metric::Score, 0.93);
+        set_metric(&mut baseline, Metric::SpeedIndex, 2480.0);
+
+        regression::compare(
+            summary,
+            &Baseline::PreviousRun,
+            &BaselineValues::from_summary(&baseline),
+            &RegressionThresholds::default(),
+        )
+    }
+
+    fn golden(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn slack_message_matches_golden_file() {
+        let summary = audit_summary();
+        let message = slack_message(&summary, Some(&comparison(&summary)));
+
+        assert_eq!(
+            message,
+            golden(include_str!("../tests/fixtures/notifications/slack.json"))
+        );
+    }
+
+    #[test]
+    fn teams_message_matches_golden_file() {
+        let summary = audit_summary();
+        let message = teams_message(&summary, Some(&comparison(&summary)));
+
+        assert_eq!(
+            message,
+            golden(include_str!("../tests/fixtures/notifications/teams.json"))
+        );
+    }
+
+    #[test]
+    fn email_matches_golden_files() {
+        let summary = audit_summary();
+        let email = email(&summary, Some(&comparison(&summary)));
+
+        assert_eq!(
+            email.subject(),
+            "Performance 86 for home: Regressed: Score, SI"
+        );
+        assert_eq!(
+            email.text(),
+            include_str!("../tests/fixtures/notifications/email.txt")
+        );
+        assert_eq!(
+            email.html(),
+            include_str!("../tests/fixtures/notifications/email.html")
+        );
+    }
+
+    #[test]
+    fn slack_mrkdwn_escapes_control_characters() {
+        let mut summary = audit_summary();
+        summary.set_page_id("<!channel> & co".to_owned());
+
+        let message = slack_message(&summary, None);
+
+        assert_eq!(
+            message["text"],
+            "Performance 86 for &lt;!channel&gt; &amp; co"
+        );
+        assert_eq!(
+            message["blocks"][0]["text"]["text"],
+            "Performance 86 for <!channel> & co"
+        );
+        let context = message["blocks"][2]["elements"][0]["text"]
+            .as_str()
+            .unwrap();
+        assert!(context.starts_with("Mobile &lt;Moto G4&gt; &amp; 4G"));
+    }
+
+    #[test]
+    fn messages_without_comparison_have_no_headline_or_deltas() {
+        let summary = audit_summary();
+
+        let message = slack_message(&summary, None);
+        let blocks = message["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            blocks[1]["fields"][0]["text"],
+            "*Performance*\n:large_orange_circle: 86"
+        );
+        assert_eq!(email(&summary, None).subject(), "Performance 86 for home");
+    }
+}
@@ -0,0 +1,294 @@
+use crate::fields::field;
+use crate::queries::SortOrder;
+use crate::{AuditSummary, MetaSite};
+use bson::{doc, oid::ObjectId, Document};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// An offset-paged list of results. Not to be confused with `crate::Page`,
+/// a page of a site.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct Page<T> {
+    items: Vec<T>,
+    page: i64,
+    page_size: i64,
+    total_items: i64,
+    total_pages: i64,
+}
+
+impl<T> Page<T> {
+    /// `page` is 1-based. Pages below 1 count as the first page, and page
+    /// sizes below 1 as a size of 1.
+    pub fn new(items: Vec<T>, page: i64, page_size: i64, total_items: i64) -> Page<T> {
+        let page_size = page_size.max(1);
+        let total_items = total_items.max(0);
+        let total_pages = total_items / page_size + i64::from(total_items % page_size != 0);
+
+        Page {
+            items,
+            page: page.max(1),
+            page_size,
+            total_items,
+            total_pages,
+        }
+    }
+
+    /// Number of documents to skip to reach a 1-based page, saturating for
+    /// pages beyond `i64::MAX` documents.
+    pub fn skip(page: i64, page_size: i64) -> i64 {
+        (page.max(1) - 1).saturating_mul(page_size.max(1))
+    }
+
+    pub fn map<U, F: FnMut(T) -> U>(self, f: F) -> Page<U> {
+        Page {
+            items: self.items.into_iter().map(f).collect(),
+            page: self.page,
+            page_size: self.page_size,
+            total_items: self.total_items,
+            total_pages: self.total_pages,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Default, Clone)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct CursorPage<T> {
+    items: Vec<T>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next_cursor: Option<String>,
+    has_more: bool,
+}
+
+impl<T: CursorKey> CursorPage<T> {
+    /// Builds a page from up to `limit + 1` fetched items; the extra item
+    /// only signals that more results exist.
+    pub fn from_items(mut items: Vec<T>, limit: usize) -> CursorPage<T> {
+        let has_more = items.len() > limit;
+        items.truncate(limit);
+        let next_cursor = if has_more {
+            items.last().and_then(CursorKey::cursor).map(|c| c.encode())
+        } else {
+            None
+        };
+
+        CursorPage {
+            items,
+            next_cursor,
+            has_more,
+        }
+    }
+}
+
+/// The sort key of the last item on a page.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cursor {
+    Id(ObjectId),
+    /// `fetch_time` with the id as a tie-breaker.
+    FetchTime {
+        fetch_time: String,
+        id: ObjectId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CursorError(String);
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid cursor: {}", self.0)
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+impl Cursor {
+    /// Encodes the cursor as URL-safe base64 of a small BSON document.
+    pub fn encode(&self) -> String {
+        let document = match self {
+            Cursor::Id(id) => doc! { "k": "id", "id": id.clone() },
+            Cursor::FetchTime { fetch_time, id } => {
+                doc! { "k": "fetchTime", "t": fetch_time.clone(), "id": id.clone() }
+            }
+        };
+        let mut bytes = Vec::new();
+        document
+            .to_writer(&mut bytes)
+            .expect("cursor is valid bson");
+
+        base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+    }
+
+    pub fn decode(cursor: &str) -> Result<Cursor, CursorError> {
+        let bytes = base64::decode_config(cursor, base64::URL_SAFE_NO_PAD)
+            .map_err(|e| CursorError(e.to_string()))?;
+        let document =
+            Document::from_reader(&mut bytes.as_slice()).map_err(|e| CursorError(e.to_string()))?;
+        let id = document
+            .get_object_id("id")
+            .map_err(|e| CursorError(e.to_string()))?
+            .clone();
+
+        match document.get_str("k") {
+            Ok("id") => Ok(Cursor::Id(id)),
+            Ok("fetchTime") => {
+                let fetch_time = document
+                    .get_str("t")
+                    .map_err(|e| CursorError(e.to_string()))?;
+                Ok(Cursor::FetchTime {
+                    fetch_time: fetch_time.to_owned(),
+                    id,
+                })
+            }
+            _ => Err(CursorError("unknown cursor kind".to_owned())),
+        }
+    }
+
+    /// Matches the documents after this cursor in the given sort order.
+    pub fn filter(&self, order: SortOrder) -> Document {
+        let operator = match order {
+            SortOrder::Ascending => "$gt",
+            SortOrder::Descending => "$lt",
+        };
+        let id_field = field::<AuditSummary>("id");
+
+        match self {
+            Cursor::Id(id) => doc! { id_field: { operator: id.clone() } },
+            Cursor::FetchTime { fetch_time, id } => {
+                let fetch_time_field = field::<AuditSummary>("fetch_time");
+                doc! {
+                    "$or": [
+                        { fetch_time_field: { operator: fetch_time.clone() } },
+                        { fetch_time_field: fetch_time.clone(), id_field: { operator: id.clone() } },
+                    ]
+                }
+            }
+        }
+    }
+}
+
+fn direction(order: SortOrder) -> i32 {
+    match order {
+        SortOrder::Ascending => 1,
+        SortOrder::Descending => -1,
+    }
+}
+
+/// Sort for paging by `Cursor::Id`, including the first page.
+pub fn id_sort(order: SortOrder) -> Document {
+    doc! { field::<AuditSummary>("id"): direction(order) }
+}
+
+/// Sort for paging by `Cursor::FetchTime`, including the first page.
+pub fn fetch_time_sort(order: SortOrder) -> Document {
+    doc! {
+        field::<AuditSummary>("fetch_time"): direction(order),
+        field::<AuditSummary>("id"): direction(order),
+    }
+}
+
+pub trait CursorKey {
+    fn cursor(&self) -> Option<Cursor>;
+}
+
+impl CursorKey for MetaSite {
+    fn cursor(&self) -> Option<Cursor> {
+        Some(Cursor::Id(self.id.clone()))
+    }
+}
+
+impl CursorKey for AuditSummary {
+    fn cursor(&self) -> Option<Cursor> {
+        self.id.as_ref().map(|id| Cursor::FetchTime {
+            fetch_time: self.fetch_time.clone(),
+            id: id.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_pages_rounds_up() {
+        assert_eq!(*Page::new(vec![(); 10], 1, 10, 25).total_pages(), 3);
+        assert_eq!(*Page::new(vec![(); 10], 1, 10, 20).total_pages(), 2);
+        assert_eq!(*Page::<()>::new(vec![], 1, 10, 0).total_pages(), 0);
+        assert_eq!(*Page::<()>::new(vec![], 1, 10, 1).total_pages(), 1);
+    }
+
+    #[test]
+    fn page_and_page_size_are_clamped() {
+        for page_size in &[0, -1, i64::MIN] {
+            let page = Page::<()>::new(vec![], 0, *page_size, 3);
+
+            assert_eq!(*page.page_size(), 1);
+            assert_eq!(*page.page(), 1);
+            assert_eq!(*page.total_pages(), 3);
+        }
+        assert_eq!(*Page::<()>::new(vec![], 1, 10, -5).total_items(), 0);
+    }
+
+    #[test]
+    fn total_pages_does_not_overflow() {
+        assert_eq!(
+            *Page::<()>::new(vec![], 1, i64::MAX, i64::MAX).total_pages(),
+            1
+        );
+        assert_eq!(
+            *Page::<()>::new(vec![], 1, 2, i64::MAX).total_pages(),
+            i64::MAX / 2 + 1
+        );
+        assert_eq!(
+            *Page::<()>::new(vec![], 1, 1, i64::MAX).total_pages(),
+            i64::MAX
+        );
+    }
+
+    #[test]
+    fn skip_counts_previous_pages() {
+        assert_eq!(Page::<()>::skip(1, 20), 0);
+        assert_eq!(Page::<()>::skip(3, 20), 40);
+        assert_eq!(Page::<()>::skip(0, 20), 0);
+        assert_eq!(Page::<()>::skip(i64::MIN, 20), 0);
+        assert_eq!(Page::<()>::skip(3, 0), 2);
+        assert_eq!(Page::<()>::skip(3, -20), 2);
+    }
+
+    #[test]
+    fn skip_saturates() {
+        assert_eq!(Page::<()>::skip(i64::MAX, 20), i64::MAX);
+        assert_eq!(Page::<()>::skip(2, i64::MAX), i64::MAX);
+        assert_eq!(Page::<()>::skip(3, i64::MAX), i64::MAX);
+    }
+
+    #[test]
+    fn map_keeps_paging() {
+        let page = Page::new(vec![1, 2], 2, 2, 5).map(|n| n * 10);
+
+        assert_eq!(page.items(), &vec![10, 20]);
+        assert_eq!(*page.page(), 2);
+        assert_eq!(*page.total_pages(), 3);
+    }
+
+    #[test]
+    fn cursor_round_trip() {
+        let id = ObjectId::new();
+        let cursors = vec![
+            Cursor::Id(id.clone()),
+            Cursor::FetchTime {
+                fetch_time: "2021-03-10T12:00:00.000Z".to_owned(),
+                id,
+            },
+        ];
+
+        for cursor in cursors {
+            assert_eq!(Cursor::decode(&cursor.encode()), Ok(cursor));
+        }
+        assert!(Cursor::decode("not a cursor").is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::fields::field;
+use crate::{AuditProfile, AuditSummary, Categories, Performance};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditSummaryField {
+    Id,
+    SiteId,
+    SiteRunId,
+    PageId,
+    AuditProfileId,
+    AuditProfile,
+    FetchTime,
+    Categories,
+    ConfigSettings,
+    WebVitals,
+    AuditDetailId,
+    Device,
+    LighthouseVersion,
+    Score,
+}
+
+impl AuditSummaryField {
+    /// Dotted document path of the field.
+    pub fn path(&self) -> String {
+        match self {
+            AuditSummaryField::Id => summary_field("id").to_owned(),
+            AuditSummaryField::SiteId => summary_field("site_id").to_owned(),
+            AuditSummaryField::SiteRunId => summary_field("site_run_id").to_owned(),
+            AuditSummaryField::PageId => summary_field("page_id").to_owned(),
+            AuditSummaryField::AuditProfileId => summary_field("audit_profile_id").to_owned(),
+            AuditSummaryField::AuditProfile => summary_field("audit_profile").to_owned(),
+            AuditSummaryField::FetchTime => summary_field("fetch_time").to_owned(),
+            AuditSummaryField::Categories => summary_field("categories").to_owned(),
+            AuditSummaryField::ConfigSettings => summary_field("config_settings").to_owned(),
+            AuditSummaryField::WebVitals => summary_field("web_vitals").to_owned(),
+            AuditSummaryField::AuditDetailId => summary_field("audit_detail_id").to_owned(),
+            AuditSummaryField::Device => format!(
+                "{}.{}",
+                summary_field("audit_profile"),
+                field::<AuditProfile>("device")
+            ),
+            AuditSummaryField::LighthouseVersion => format!(
+                "{}.{}",
+                summary_field("audit_profile"),
+                field::<AuditProfile>("lighthouse_version")
+            ),
+            AuditSummaryField::Score => format!(
+                "{}.{}.{}",
+                summary_field("categories"),
+                field::<Categories>("performance"),
+                field::<Performance>("score")
+            ),
+        }
+    }
+}
+
+fn summary_field(rust_name: &'static str) -> &'static str {
+    field::<AuditSummary>(rust_name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// Builds filter, sort and projection documents for `auditSummaries`.
+///
+/// Fetch times are compared as ISO 8601 strings, which is how Lighthouse
+/// reports them.
+#[derive(Debug, Default, Clone)]
+pub struct AuditSummaryQuery {
+    site_id: Option<ObjectId>,
+    page_id: Option<String>,
+    audit_profile_id: Option<String>,
+    min_site_run_id: Option<i32>,
+    max_site_run_id: Option<i32>,
+    fetched_from: Option<DateTime<Utc>>,
+    fetched_until: Option<DateTime<Utc>>,
+    lighthouse_version: Option<String>,
+    device: Option<String>,
+    sort: Vec<(AuditSummaryField, SortOrder)>,
+    projection: Vec<AuditSummaryField>,
+    limit: Option<i64>,
+}
+
+impl AuditSummaryQuery {
+    pub fn new() -> AuditSummaryQuery {
+        AuditSummaryQuery::default()
+    }
+
+    pub fn site(mut self, site_id: ObjectId) -> AuditSummaryQuery {
+        self.site_id = Some(site_id);
+        self
+    }
+
+    pub fn page(mut self, page_id: &str) -> AuditSummaryQuery {
+        self.page_id = Some(page_id.to_owned());
+        self
+    }
+
+    pub fn audit_profile(mut self, audit_profile_id: &str) -> AuditSummaryQuery {
+        self.audit_profile_id = Some(audit_profile_id.to_owned());
+        self
+    }
+
+    /// Inclusive range of site run ids.
+    pub fn site_runs(mut self, min: Option<i32>, max: Option<i32>) -> AuditSummaryQuery {
+        self.min_site_run_id = min;
+        self.max_site_run_id = max;
+        self
+    }
+
+    /// Summaries fetched in `[from, until)`.
+    pub fn fetched_between(
+        mut self,
+        from: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> AuditSummaryQuery {
+        self.fetched_from = from;
+        self.fetched_until = until;
+        self
+    }
+
+    /// Summaries fetched in the `days` before `now`. A window reaching past
+    /// the earliest representable time has no lower bound.
+    pub fn last_days(self, days: i64, now: DateTime<Utc>) -> AuditSummaryQuery {
+        let from = Duration::try_days(days).and_then(|window| now.checked_sub_signed(window));
+        self.fetched_between(from, None)
+    }
+
+    pub fn lighthouse_version(mut self, lighthouse_version: &str) -> AuditSummaryQuery {
+        self.lighthouse_version = Some(lighthouse_version.to_owned());
+        self
+    }
+
+    pub fn device(mut self, device: &str) -> AuditSummaryQuery {
+        self.device = Some(device.to_owned());
+        self
+    }
+
+    pub fn sort_by(mut self, field: AuditSummaryField, order: SortOrder) -> AuditSummaryQuery {
+        self.sort.push((field, order));
+        self
+    }
+
+    pub fn newest_first(self) -> AuditSummaryQuery {
+        self.sort_by(AuditSummaryField::FetchTime, SortOrder::Descending)
+    }
+
+    /// Restricts the returned fields; `_id` is always returned by MongoDB.
+    pub fn include(mut self, field: AuditSummaryField) -> AuditSummaryQuery {
+        self.projection.push(field);
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> AuditSummaryQuery {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn filter(&self) -> Document {
+        let mut filter = Document::new();
+
+        if let Some(site_id) = &self.site_id {
+            filter.insert(AuditSummaryField::SiteId.path(), site_id.clone());
+        }
+        if let Some(page_id) = &self.page_id {
+            filter.insert(AuditSummaryField::PageId.path(), page_id.clone());
+        }
+        if let Some(audit_profile_id) = &self.audit_profile_id {
+            filter.insert(
+                AuditSummaryField::AuditProfileId.path(),
+                audit_profile_id.clone(),
+            );
+        }
+        if let Some(range) = range(
+            "$gte",
+            self.min_site_run_id.map(Bson::from),
+            "$lte",
+            self.max_site_run_id.map(Bson::from),
+        ) {
+            filter.insert(AuditSummaryField::SiteRunId.path(), range);
+        }
+        if let Some(range) = range(
+            "$gte",
+            self.fetched_from.map(|t| Bson::from(fetch_time(t))),
+            "$lt",
+            self.fetched_until.map(|t| Bson::from(fetch_time(t))),
+        ) {
+            filter.insert(AuditSummaryField::FetchTime.path(), range);
+        }
+        if let Some(lighthouse_version) = &self.lighthouse_version {
+            filter.insert(
+                AuditSummaryField::LighthouseVersion.path(),
+                lighthouse_version.clone(),
+            );
+        }
+        if let Some(device) = &self.device {
+            filter.insert(AuditSummaryField::Device.path(), device.clone());
+        }
+
+        filter
+    }
+
+    pub fn sort(&self) -> Option<Document> {
+        if self.sort.is_empty() {
+            return None;
+        }
+
+        let mut sort = Document::new();
+        for (field, order) in &self.sort {
+            let direction = match order {
+                SortOrder::Ascending => 1,
+                SortOrder::Descending => -1,
+            };
+            sort.insert(field.path(), direction);
+        }
+        Some(sort)
+    }
+
+    pub fn projection(&self) -> Option<Document> {
+        if self.projection.is_empty() {
+            return None;
+        }
+
+        let mut projection = Document::new();
+        for field in &self.projection {
+            projection.insert(field.path(), 1);
+        }
+        Some(projection)
+    }
+
+    pub fn limit_value(&self) -> Option<i64> {
+        self.limit
+    }
+
+    /// The equivalent `find` database command.
+    pub fn to_command(&self, collection: &str) -> Document {
+        let mut command = doc! {
+            "find": collection,
+            "filter": self.filter(),
+        };
+        if let Some(sort) = self.sort() {
+            command.insert("sort", sort);
+        }
+        if let Some(projection) = self.projection() {
+            command.insert("projection", projection);
+        }
+        if let Some(limit) = self.limit {
+            command.insert("limit", limit);
+        }
+        command
+    }
+}
+
+fn range(
+    lower_operator: &str,
+    lower: Option<Bson>,
+    upper_operator: &str,
+    upper: Option<Bson>,
+) -> Option<Document> {
+    let mut range = Document::new();
+    if let Some(lower) = lower {
+        range.insert(lower_operator, lower);
+    }
+    if let Some(upper) = upper {
+        range.insert(upper_operator, upper);
+    }
+
+    if range.is_empty() {
+        None
+    } else {
+        Some(range)
+    }
+}
+
+/// Formats a time the way Lighthouse writes `fetchTime`.
+pub fn fetch_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 3, 10, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn field_paths_use_serialized_names() {
+        assert_eq!(AuditSummaryField::Id.path(), "_id");
+        assert_eq!(AuditSummaryField::SiteRunId.path(), "siteRunId");
+        assert_eq!(
+            AuditSummaryField::LighthouseVersion.path(),
+            "auditProfile.lighthouseVersion"
+        );
+        assert_eq!(
+            AuditSummaryField::Score.path(),
+            "categories.performance.score"
+        );
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        let query = AuditSummaryQuery::new();
+
+        assert_eq!(query.filter(), Document::new());
+        assert_eq!(query.sort(), None);
+        assert_eq!(query.projection(), None);
+    }
+
+    #[test]
+    fn filter_combines_criteria() {
+        let site_id = ObjectId::new();
+        let query = AuditSummaryQuery::new()
+            .site(site_id.clone())
+            .page("home")
+            .site_runs(Some(3), None)
+            .device("mobile");
+
+        assert_eq!(
+            query.filter(),
+            doc! {
+                "siteId": site_id,
+                "pageId": "home",
+                "siteRunId": { "$gte": 3 },
+                "auditProfile.device": "mobile",
+            }
+        );
+    }
+
+    #[test]
+    fn last_days_filters_on_fetch_time() {
+        let query = AuditSummaryQuery::new().last_days(7, now());
+
+        assert_eq!(
+            query.filter(),
+            doc! { "fetchTime": { "$gte": "2021-03-03T12:00:00.000Z" } }
+        );
+    }
+
+    #[test]
+    fn last_days_out_of_range_has_no_lower_bound() {
+        for days in &[i64::MAX, i64::MIN, 1 << 40] {
+            let query = AuditSummaryQuery::new().last_days(*days, now());
+
+            assert_eq!(query.filter(), Document::new(), "days = {}", days);
+        }
+    }
+
+    #[test]
+    fn to_command_includes_sort_projection_and_limit() {
+        let query = AuditSummaryQuery::new()
+            .newest_first()
+            .include(AuditSummaryField::Score)
+            .limit(10);
+
+        assert_eq!(
+            query.to_command("auditSummaries"),
+            doc! {
+                "find": "auditSummaries",
+                "filter": {},
+                "sort": { "fetchTime": -1 },
+                "projection": { "categories.performance.score": 1 },
+                "limit": 10_i64,
+            }
+        );
+    }
+}
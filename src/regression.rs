@@ -0,0 +1,454 @@
+use crate::metrics::{self, metric_values, Metric, Rating};
+use crate::AuditSummary;
+use bson::oid::ObjectId;
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Baseline {
+    PreviousRun,
+    Pinned { audit_summary_id: ObjectId },
+    RollingMedian { runs: usize },
+}
+
+/// A change is significant only when it exceeds both the absolute and the
+/// relative threshold.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, Copy, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct Threshold {
+    absolute: f64,
+    relative: f64,
+}
+
+impl Threshold {
+    pub fn new(absolute: f64, relative: f64) -> Threshold {
+        Threshold { absolute, relative }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone)]
+#[getset(get = "pub", set = "pub")]
+pub struct RegressionThresholds {
+    thresholds: BTreeMap<Metric, Threshold>,
+}
+
+impl RegressionThresholds {
+    pub fn threshold(&self, metric: Metric) -> Threshold {
+        self.thresholds
+            .get(&metric)
+            .copied()
+            .unwrap_or_else(|| default_threshold(metric))
+    }
+
+    pub fn set_threshold(&mut self, metric: Metric, threshold: Threshold) {
+        self.thresholds.insert(metric, threshold);
+    }
+}
+
+impl Default for RegressionThresholds {
+    fn default() -> RegressionThresholds {
+        RegressionThresholds {
+            thresholds: Metric::all()
+                .iter()
+                .map(|metric| (*metric, default_threshold(*metric)))
+                .collect(),
+        }
+    }
+}
+
+/// 5 points of score, 0.02 of CLS or 100 ms, each also at least 10% (5% for
+/// the score).
+fn default_threshold(metric: Metric) -> Threshold {
+    match metric {
+        Metric::Score => Threshold::new(0.05, 0.05),
+        Metric::CumulativeLayoutShift => Threshold::new(0.02, 0.1),
+        _ => Threshold::new(100.0, 0.1),
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum Verdict {
+    Improved,
+    Unchanged,
+    Regressed,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct MetricDelta {
+    metric: Metric,
+    baseline: f64,
+    current: f64,
+    delta: f64,
+    relative_delta: Option<f64>,
+    baseline_rating: Rating,
+    current_rating: Rating,
+    verdict: Verdict,
+}
+
+impl MetricDelta {
+    pub fn new(metric: Metric, baseline: f64, current: f64, threshold: Threshold) -> MetricDelta {
+        let delta = current - baseline;
+        let relative_delta = if baseline != 0.0 {
+            Some(delta / baseline.abs())
+        } else {
+            None
+        };
+        let significant = delta.abs() >= threshold.absolute
+            && relative_delta.is_none_or(|r| r.abs() >= threshold.relative);
+        let verdict = if !significant {
+            Verdict::Unchanged
+        } else if (delta > 0.0) == metric.higher_is_better() {
+            Verdict::Improved
+        } else {
+            Verdict::Regressed
+        };
+
+        MetricDelta {
+            metric,
+            baseline,
+            current,
+            delta,
+            relative_delta,
+            baseline_rating: metric.rating(baseline),
+            current_rating: metric.rating(current),
+            verdict,
+        }
+    }
+
+    pub fn rating_changed(&self) -> bool {
+        self.baseline_rating != self.current_rating
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct RegressionReport {
+    baseline: Baseline,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline_audit_summary_id: Option<ObjectId>,
+    score: Option<MetricDelta>,
+    metrics: Vec<MetricDelta>,
+    verdict: Verdict,
+}
+
+impl RegressionReport {
+    /// Deltas for the score and every metric, score first.
+    pub fn deltas(&self) -> impl Iterator<Item = &MetricDelta> {
+        self.score.iter().chain(self.metrics.iter())
+    }
+
+    pub fn delta(&self, metric: Metric) -> Option<&MetricDelta> {
+        self.deltas().find(|d| d.metric == metric)
+    }
+
+    pub fn regressions(&self) -> impl Iterator<Item = &MetricDelta> {
+        self.deltas().filter(|d| d.verdict == Verdict::Regressed)
+    }
+}
+
+/// Metric values of a baseline, which for a rolling median do not belong to
+/// a single run.
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct BaselineValues {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_summary_id: Option<ObjectId>,
+    values: BTreeMap<Metric, f64>,
+}
+
+impl BaselineValues {
+    pub fn from_summary(summary: &AuditSummary) -> BaselineValues {
+        BaselineValues {
+            audit_summary_id: summary.id().clone(),
+            values: metric_values(summary),
+        }
+    }
+}
+
+/// Picks the baseline for `current` from earlier summaries of the same page
+/// and audit profile in `history`.
+pub fn select_baseline(
+    current: &AuditSummary,
+    history: &[AuditSummary],
+    baseline: &Baseline,
+) -> Option<BaselineValues> {
+    let mut previous: Vec<&AuditSummary> = history
+        .iter()
+        .filter(|s| {
+            s.site_id() == current.site_id()
+                && s.page_id() == current.page_id()
+                && s.audit_profile_id() == current.audit_profile_id()
+                && s.fetch_time() < current.fetch_time()
+        })
+        .collect();
+    previous.sort_by(|a, b| a.fetch_time().cmp(b.fetch_time()));
+
+    match baseline {
+        Baseline::PreviousRun => previous
+            .last()
+            .map(|summary| BaselineValues::from_summary(summary)),
+        Baseline::Pinned { audit_summary_id } => history
+            .iter()
+            .find(|s| s.id().as_ref() == Some(audit_summary_id))
+            .map(BaselineValues::from_summary),
+        Baseline::RollingMedian { runs } => {
+            let window = &previous[previous.len().saturating_sub(*runs)..];
+            if window.is_empty() {
+                return None;
+            }
+
+            let values = Metric::all()
+                .iter()
+                .filter_map(|metric| {
+                    let values: Vec<f64> = window.iter().filter_map(|s| metric.value(s)).collect();
+                    metrics::median(&values).map(|median| (*metric, median))
+                })
+                .collect();
+            Some(BaselineValues {
+                audit_summary_id: None,
+                values,
+            })
+        }
+    }
+}
+
+pub fn compare(
+    current: &AuditSummary,
+    baseline: &Baseline,
+    baseline_values: &BaselineValues,
+    thresholds: &RegressionThresholds,
+) -> RegressionReport {
+    let mut score = None;
+    let mut metrics = Vec::new();
+
+    for (metric, current_value) in metric_values(current) {
+        let baseline_value = match baseline_values.values.get(&metric) {
+            Some(value) => *value,
+            None => continue,
+        };
+        let delta = MetricDelta::new(
+            metric,
+            baseline_value,
+            current_value,
+            thresholds.threshold(metric),
+        );
+        if metric == Metric::Score {
+            score = Some(delta);
+        } else {
+            metrics.push(delta);
+        }
+    }
+
+    let verdicts: Vec<Verdict> = score
+        .iter()
+        .chain(metrics.iter())
+        .map(|d| d.verdict)
+        .collect();
+    let verdict = if verdicts.contains(&Verdict::Regressed) {
+        Verdict::Regressed
+    } else if verdicts.contains(&Verdict::Improved) {
+        Verdict::Improved
+    } else {
+        Verdict::Unchanged
+    };
+
+    RegressionReport {
+        baseline: baseline.clone(),
+        baseline_audit_summary_id: baseline_values.audit_summary_id.clone(),
+        score,
+        metrics,
+        verdict,
+    }
+}
+
+/// Selects the baseline from `history` and compares against it; `None` when
+/// no baseline is available.
+pub fn detect(
+    current: &AuditSummary,
+    history: &[AuditSummary],
+    baseline: &Baseline,
+    thresholds: &RegressionThresholds,
+) -> Option<RegressionReport> {
+    select_baseline(current, history, baseline)
+        .map(|values| compare(current, baseline, &values, thresholds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{set_metric, summary};
+    use bson::oid::ObjectId;
+
+    fn verdict(metric: Metric, baseline: f64, current: f64) -> Verdict {
+        *MetricDelta::new(
+            metric,
+            baseline,
+            current,
+            RegressionThresholds::default().threshold(metric),
+        )
+        .verdict()
+    }
+
+    #[test]
+    fn changes_must_exceed_both_thresholds() {
+        // 90 ms is under the absolute threshold of 100 ms.
+        assert_eq!(
+            verdict(Metric::SpeedIndex, 300.0, 390.0),
+            Verdict::Unchanged
+        );
+        // 200 ms is only 6.7%, under the relative threshold of 10%.
+        assert_eq!(
+            verdict(Metric::SpeedIndex, 3000.0, 3200.0),
+            Verdict::Unchanged
+        );
+        assert_eq!(
+            verdict(Metric::SpeedIndex, 3000.0, 3400.0),
+            Verdict::Regressed
+        );
+        assert_eq!(
+            verdict(Metric::SpeedIndex, 3000.0, 2600.0),
+            Verdict::Improved
+        );
+    }
+
+    #[test]
+    fn change_at_both_thresholds_is_significant() {
+        assert_eq!(
+            verdict(Metric::SpeedIndex, 1000.0, 1100.0),
+            Verdict::Regressed
+        );
+        assert_eq!(
+            verdict(Metric::SpeedIndex, 1000.0, 1099.0),
+            Verdict::Unchanged
+        );
+    }
+
+    #[test]
+    fn score_is_better_when_higher() {
+        assert_eq!(verdict(Metric::Score, 0.8, 0.9), Verdict::Improved);
+        assert_eq!(verdict(Metric::Score, 0.9, 0.8), Verdict::Regressed);
+        assert_eq!(verdict(Metric::Score, 0.9, 0.88), Verdict::Unchanged);
+    }
+
+    #[test]
+    fn zero_baseline_uses_the_absolute_threshold_only() {
+        let delta = MetricDelta::new(
+            Metric::CumulativeLayoutShift,
+            0.0,
+            0.03,
+            RegressionThresholds::default().threshold(Metric::CumulativeLayoutShift),
+        );
+
+        assert_eq!(*delta.relative_delta(), None);
+        assert_eq!(*delta.verdict(), Verdict::Regressed);
+    }
+
+    #[test]
+    fn custom_threshold_overrides_the_default() {
+        let mut thresholds = RegressionThresholds::default();
+        thresholds.set_threshold(Metric::SpeedIndex, Threshold::new(500.0, 0.0));
+
+        assert_eq!(
+            thresholds.threshold(Metric::SpeedIndex),
+            Threshold::new(500.0, 0.0)
+        );
+        assert_eq!(
+            thresholds.threshold(Metric::Interactive),
+            Threshold::new(100.0, 0.1)
+        );
+    }
+
+    #[test]
+    fn report_verdict_is_regressed_if_any_metric_regressed() {
+        let site = ObjectId::new();
+        let baseline = summary(&site, "2021-01-01T00:00:00Z", 0.8);
+        let mut current = summary(&site, "2021-01-02T00:00:00Z", 0.9);
+        let history = vec![baseline.clone()];
+        let thresholds = RegressionThresholds::default();
+
+        let report = detect(&current, &history, &Baseline::PreviousRun, &thresholds).unwrap();
+        assert_eq!(*report.verdict(), Verdict::Improved);
+        assert_eq!(report.baseline_audit_summary_id(), baseline.id());
+        assert_eq!(report.regressions().count(), 0);
+
+        set_metric(&mut current, Metric::SpeedIndex, 5000.0);
+        let report = detect(&current, &history, &Baseline::PreviousRun, &thresholds).unwrap();
+        assert_eq!(*report.verdict(), Verdict::Regressed);
+        let regressions: Vec<Metric> = report.regressions().map(|d| *d.metric()).collect();
+        assert_eq!(regressions, vec![Metric::SpeedIndex]);
+        assert_eq!(
+            *report.delta(Metric::Score).unwrap().verdict(),
+            Verdict::Improved
+        );
+    }
+
+    #[test]
+    fn report_verdict_is_unchanged_within_thresholds() {
+        let site = ObjectId::new();
+        let current = summary(&site, "2021-01-02T00:00:00Z", 0.86);
+        let history = vec![summary(&site, "2021-01-01T00:00:00Z", 0.85)];
+
+        let report = detect(
+            &current,
+            &history,
+            &Baseline::PreviousRun,
+            &RegressionThresholds::default(),
+        )
+        .unwrap();
+
+        assert_eq!(*report.verdict(), Verdict::Unchanged);
+    }
+
+    #[test]
+    fn baseline_selection() {
+        let site = ObjectId::new();
+        let current = summary(&site, "2021-01-03T00:00:00Z", 0.9);
+        let pinned = summary(&site, "2021-01-01T00:00:00Z", 0.6);
+        let history = vec![
+            summary(&site, "2020-12-31T00:00:00Z", 0.5),
+            pinned.clone(),
+            summary(&site, "2021-01-02T00:00:00Z", 0.7),
+            summary(&site, "2021-01-04T00:00:00Z", 0.2),
+        ];
+        let score = |baseline: &Baseline| {
+            select_baseline(&current, &history, baseline)
+                .and_then(|values| values.values().get(&Metric::Score).copied())
+        };
+
+        assert_eq!(score(&Baseline::PreviousRun), Some(0.7));
+        assert_eq!(
+            score(&Baseline::Pinned {
+                audit_summary_id: pinned.id().clone().unwrap()
+            }),
+            Some(0.6)
+        );
+        assert_eq!(score(&Baseline::RollingMedian { runs: 3 }), Some(0.6));
+        assert_eq!(select_baseline(&current, &[], &Baseline::PreviousRun), None);
+    }
+
+    #[test]
+    fn baseline_ignores_other_sites_with_the_same_page_ids() {
+        let site = ObjectId::new();
+        let other_site = ObjectId::new();
+        let current = summary(&site, "2021-01-03T00:00:00Z", 0.9);
+        let history = vec![
+            summary(&site, "2021-01-01T00:00:00Z", 0.5),
+            summary(&other_site, "2021-01-02T00:00:00Z", 0.1),
+        ];
+
+        let baseline = select_baseline(&current, &history, &Baseline::PreviousRun).unwrap();
+        assert_eq!(baseline.values().get(&Metric::Score), Some(&0.5));
+
+        let median =
+            select_baseline(&current, &history, &Baseline::RollingMedian { runs: 5 }).unwrap();
+        assert_eq!(median.values().get(&Metric::Score), Some(&0.5));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::lh_models::{Audit, Opportunity};
+use crate::metrics::{Metric, Rating};
+use crate::regression::{RegressionReport, Verdict};
+use crate::{AuditDetail, AuditSummary};
+
+/// One line of a rendered summary.
+pub(crate) struct Row {
+    pub(crate) metric: Metric,
+    pub(crate) value: String,
+    pub(crate) rating: Rating,
+    pub(crate) delta: Option<String>,
+    pub(crate) verdict: Option<Verdict>,
+}
+
+/// The score followed by the web vitals present in the summary.
+pub(crate) fn rows(summary: &AuditSummary, comparison: Option<&RegressionReport>) -> Vec<Row> {
+    std::iter::once(Metric::Score)
+        .chain(Metric::web_vitals().iter().copied())
+        .filter_map(|metric| {
+            let value = metric.value(summary)?;
+            let delta = comparison.and_then(|report| report.delta(metric));
+            Some(Row {
+                metric,
+                value: metric.format(value),
+                rating: metric.rating(value),
+                delta: delta.map(|d| metric.format_delta(*d.delta())),
+                verdict: delta.map(|d| *d.verdict()),
+            })
+        })
+        .collect()
+}
+
+/// A one-line verdict of the comparison, if any.
+pub(crate) fn headline(comparison: Option<&RegressionReport>) -> Option<String> {
+    comparison.map(|report| {
+        let regressions: Vec<&str> = report
+            .regressions()
+            .map(|delta| delta.metric().abbreviation())
+            .collect();
+        match report.verdict() {
+            Verdict::Regressed => format!("Regressed: {}", regressions.join(", ")),
+            Verdict::Improved => "Improved against the baseline".to_owned(),
+            Verdict::Unchanged => "No significant change against the baseline".to_owned(),
+        }
+    })
+}
+
+/// ▲ for better and ▼ for worse, whatever the sign of the change.
+fn verdict_arrow(verdict: Verdict) -> &'static str {
+    match verdict {
+        Verdict::Improved => "▲",
+        Verdict::Unchanged => "",
+        Verdict::Regressed => "▼",
+    }
+}
+
+pub(crate) fn delta_text(row: &Row) -> String {
+    match (&row.delta, row.verdict) {
+        (Some(delta), Some(verdict)) if verdict != Verdict::Unchanged => {
+            format!(" {} {}", verdict_arrow(verdict), delta)
+        }
+        (Some(delta), _) => format!(" {}", delta),
+        _ => String::new(),
+    }
+}
+
+/// Opportunities with items, largest estimated savings first. Savings are
+/// the sum of the items' `wasted_ms`, which is what the report shows per
+/// row, rather than the audit's own `numericValue`.
+pub fn ranked_opportunities(detail: &AuditDetail) -> Vec<(&Audit<Opportunity>, f64)> {
+    let mut ranked: Vec<(&Audit<Opportunity>, f64)> = detail
+        .opportunities()
+        .into_iter()
+        .filter(|audit| !audit.details().items().is_empty())
+        .map(|audit| {
+            let savings = audit
+                .details()
+                .items()
+                .iter()
+                .filter_map(|item| *item.wasted_ms())
+                .sum::<i64>() as f64;
+            (audit, savings)
+        })
+        .collect();
+    ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detail() -> AuditDetail {
+        serde_json::from_str(include_str!("../tests/fixtures/audit_detail.json")).unwrap()
+    }
+
+    #[test]
+    fn ranks_opportunities_by_summed_wasted_ms() {
+        let detail = detail();
+
+        let ranked: Vec<(&str, f64)> = ranked_opportunities(&detail)
+            .into_iter()
+            .map(|(audit, savings)| (audit.id().as_str(), savings))
+            .collect();
+
+        // Render-blocking resources report the larger numericValue (900 ms)
+        // but save less per item.
+        assert_eq!(
+            ranked,
+            vec![
+                ("unused-javascript", 450.0),
+                ("render-blocking-resources", 400.0)
+            ]
+        );
+    }
+}
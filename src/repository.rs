@@ -0,0 +1,503 @@
+pub mod memory;
+#[cfg(feature = "mongodb")]
+pub mod mongo;
+
+pub use memory::InMemoryRepository;
+#[cfg(feature = "mongodb")]
+pub use mongo::MongoRepository;
+
+use crate::collections::{self, IndexDefinition};
+use crate::fields::{field, serde_fields};
+use crate::migrations::{self, Migration, MigrationError};
+use crate::{AuditDetail, AuditSummary, MetaSite, Site, SiteRun};
+use bson::{oid::ObjectId, Bson, Document};
+use getset::{Getters, Setters};
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Getters, Setters, Default, Clone)]
+#[getset(get = "pub", set = "pub")]
+pub struct RecordFilter {
+    site_id: Option<ObjectId>,
+    page_id: Option<String>,
+    audit_profile_id: Option<String>,
+}
+
+impl RecordFilter {
+    pub fn for_site(site_id: ObjectId) -> RecordFilter {
+        RecordFilter {
+            site_id: Some(site_id),
+            page_id: None,
+            audit_profile_id: None,
+        }
+    }
+
+    pub fn for_page(site_id: ObjectId, page_id: String) -> RecordFilter {
+        RecordFilter {
+            site_id: Some(site_id),
+            page_id: Some(page_id),
+            audit_profile_id: None,
+        }
+    }
+
+    pub fn for_profile(
+        site_id: ObjectId,
+        page_id: String,
+        audit_profile_id: String,
+    ) -> RecordFilter {
+        RecordFilter {
+            site_id: Some(site_id),
+            page_id: Some(page_id),
+            audit_profile_id: Some(audit_profile_id),
+        }
+    }
+}
+
+/// A model stored in its own collection. `matches` and `filter_document` must
+/// agree, so the in-memory and MongoDB repositories return the same records.
+pub trait Record: Serialize + DeserializeOwned + Clone {
+    const COLLECTION: &'static str;
+
+    /// Stored documents below this version are upgraded on read.
+    const SCHEMA_VERSION: i32 = 0;
+
+    fn migrations() -> Vec<Migration> {
+        Vec::new()
+    }
+
+    fn record_id(&self) -> Option<&ObjectId>;
+
+    fn assign_id(&mut self, id: ObjectId);
+
+    fn matches(&self, filter: &RecordFilter) -> bool;
+
+    fn filter_document(filter: &RecordFilter) -> Document;
+
+    /// The `(page_id, audit_profile_id)` a record belongs to, if any.
+    fn page_key(&self) -> Option<(&str, &str)> {
+        None
+    }
+
+    /// ISO 8601 time used to pick the latest record for a page.
+    fn recency(&self) -> Option<&str> {
+        None
+    }
+}
+
+pub trait Repository<T: Record> {
+    fn insert(&self, record: T) -> Result<ObjectId, RepositoryError>;
+
+    fn get(&self, id: &ObjectId) -> Result<Option<T>, RepositoryError>;
+
+    fn list(&self, filter: &RecordFilter) -> Result<Vec<T>, RepositoryError>;
+
+    fn delete(&self, id: &ObjectId) -> Result<bool, RepositoryError>;
+
+    fn ensure_index(&self, index: &IndexDefinition) -> Result<(), RepositoryError>;
+
+    /// The most recent record for each page and audit profile matching the
+    /// filter. Records without a page key are not returned.
+    fn latest_per_page(&self, filter: &RecordFilter) -> Result<Vec<T>, RepositoryError> {
+        Ok(latest_per_page(self.list(filter)?))
+    }
+
+    /// Reads a projection of the records matching the filter. Only the
+    /// projected fields are read, and projections cannot be written back.
+    fn project<P: Projection<T>>(&self, filter: &RecordFilter) -> Result<Vec<P>, RepositoryError> {
+        self.list(filter)?
+            .iter()
+            .map(|record| Ok(bson::from_document(bson::to_document(record)?)?))
+            .collect()
+    }
+}
+
+/// A read-only view of a record, such as `MetaSite` over `Site`. It is read
+/// with `Repository::project` and is deliberately not a `Record`, so a partial
+/// document can never be inserted into the record's collection.
+pub trait Projection<T: Record>: DeserializeOwned {
+    /// The MongoDB projection for the view's fields.
+    fn projection() -> Document {
+        serde_fields::<Self>()
+            .iter()
+            .map(|field| ((*field).to_owned(), Bson::Int32(1)))
+            .collect()
+    }
+}
+
+impl Projection<Site> for MetaSite {}
+
+/// Creates the recommended indexes for the repository's collection.
+pub fn ensure_indexes<T: Record, R: Repository<T>>(repository: &R) -> Result<(), RepositoryError> {
+    for index in collections::indexes_for(T::COLLECTION) {
+        repository.ensure_index(&index)?;
+    }
+
+    Ok(())
+}
+
+pub fn latest_per_page<T: Record>(records: Vec<T>) -> Vec<T> {
+    let mut latest: HashMap<(String, String), T> = HashMap::new();
+
+    for record in records {
+        let key = match record.page_key() {
+            Some((page_id, audit_profile_id)) => (page_id.to_owned(), audit_profile_id.to_owned()),
+            None => continue,
+        };
+
+        match latest.get(&key) {
+            Some(current) if current.recency() >= record.recency() => {}
+            _ => {
+                latest.insert(key, record);
+            }
+        }
+    }
+
+    let mut records: Vec<T> = latest.into_values().collect();
+    records.sort_by(|a, b| a.page_key().cmp(&b.page_key()));
+    records
+}
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    DuplicateId(ObjectId),
+    Serialization(bson::ser::Error),
+    Deserialization(bson::de::Error),
+    Migration(MigrationError),
+    #[cfg(feature = "mongodb")]
+    Database(mongodb::error::Error),
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepositoryError::DuplicateId(id) => write!(f, "a record with id {} already exists", id),
+            RepositoryError::Serialization(e) => write!(f, "failed to serialize record: {}", e),
+            RepositoryError::Deserialization(e) => write!(f, "failed to deserialize record: {}", e),
+            RepositoryError::Migration(e) => write!(f, "failed to migrate record: {}", e),
+            #[cfg(feature = "mongodb")]
+            RepositoryError::Database(e) => write!(f, "database error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl From<bson::ser::Error> for RepositoryError {
+    fn from(e: bson::ser::Error) -> RepositoryError {
+        RepositoryError::Serialization(e)
+    }
+}
+
+impl From<bson::de::Error> for RepositoryError {
+    fn from(e: bson::de::Error) -> RepositoryError {
+        RepositoryError::Deserialization(e)
+    }
+}
+
+impl From<MigrationError> for RepositoryError {
+    fn from(e: MigrationError) -> RepositoryError {
+        RepositoryError::Migration(e)
+    }
+}
+
+#[cfg(feature = "mongodb")]
+impl From<mongodb::error::Error> for RepositoryError {
+    fn from(e: mongodb::error::Error) -> RepositoryError {
+        RepositoryError::Database(e)
+    }
+}
+
+fn matches_value<T: PartialEq>(expected: &Option<T>, actual: &T) -> bool {
+    expected.as_ref().is_none_or(|expected| expected == actual)
+}
+
+fn matches_optional<T: PartialEq>(expected: &Option<T>, actual: &Option<T>) -> bool {
+    expected.is_none() || expected == actual
+}
+
+fn filter_document(fields: &[(&str, Option<bson::Bson>)]) -> Document {
+    let mut filter = Document::new();
+    for (field, value) in fields {
+        if let Some(value) = value {
+            filter.insert(*field, value.clone());
+        }
+    }
+    filter
+}
+
+/// A record belonging to a site and, unless it covers all of them, to one
+/// page and audit profile. Such records implement `matches` and
+/// `filter_document` with `scope_matches` and `scope_filter_document`.
+pub(crate) trait Scoped: Record {
+    fn scope(&self) -> (&ObjectId, Option<&str>, Option<&str>);
+}
+
+/// Whether the record is in the filter's scope. A record without a page or
+/// audit profile covers all of them, so it is in every page's scope.
+pub(crate) fn scope_matches<T: Scoped>(record: &T, filter: &RecordFilter) -> bool {
+    let (site_id, page_id, audit_profile_id) = record.scope();
+    matches_value(&filter.site_id, site_id)
+        && matches_scope(&filter.page_id, page_id)
+        && matches_scope(&filter.audit_profile_id, audit_profile_id)
+}
+
+fn matches_scope(expected: &Option<String>, actual: Option<&str>) -> bool {
+    expected
+        .as_ref()
+        .is_none_or(|expected| actual.is_none_or(|actual| actual == expected))
+}
+
+/// The filter on the record's serialized `site_id`, `page_id` and
+/// `audit_profile_id` fields, matching records without a page or audit
+/// profile as `scope_matches` does.
+pub(crate) fn scope_filter_document<T: Scoped>(filter: &RecordFilter) -> Document {
+    let or_unscoped = |id: &String| Bson::from(bson::doc! { "$in": [id.clone(), Bson::Null] });
+    filter_document(&[
+        (
+            field::<T>("site_id"),
+            filter.site_id.clone().map(Into::into),
+        ),
+        (
+            field::<T>("page_id"),
+            filter.page_id.as_ref().map(or_unscoped),
+        ),
+        (
+            field::<T>("audit_profile_id"),
+            filter.audit_profile_id.as_ref().map(or_unscoped),
+        ),
+    ])
+}
+
+impl Record for Site {
+    const COLLECTION: &'static str = collections::SITES;
+    const SCHEMA_VERSION: i32 = migrations::SITE_SCHEMA_VERSION;
+
+    fn migrations() -> Vec<Migration> {
+        migrations::site_migrations()
+    }
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        matches_optional(&filter.site_id, &self.id)
+            && filter
+                .page_id
+                .as_ref()
+                .is_none_or(|page_id| self.pages.iter().any(|p| p.id() == page_id))
+            && filter
+                .audit_profile_id
+                .as_ref()
+                .is_none_or(|audit_profile_id| {
+                    self.audit_profiles
+                        .iter()
+                        .any(|p| p.id() == audit_profile_id)
+                })
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        filter_document(&[
+            ("_id", filter.site_id.clone().map(Into::into)),
+            ("pages.id", filter.page_id.clone().map(Into::into)),
+            (
+                "auditProfiles.id",
+                filter.audit_profile_id.clone().map(Into::into),
+            ),
+        ])
+    }
+}
+
+impl Record for AuditSummary {
+    const COLLECTION: &'static str = collections::AUDIT_SUMMARIES;
+    const SCHEMA_VERSION: i32 = migrations::AUDIT_SUMMARY_SCHEMA_VERSION;
+
+    fn migrations() -> Vec<Migration> {
+        migrations::audit_summary_migrations()
+    }
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        matches_value(&filter.site_id, &self.site_id)
+            && matches_value(&filter.page_id, &self.page_id)
+            && matches_value(&filter.audit_profile_id, &self.audit_profile_id)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        filter_document(&[
+            (
+                field::<AuditSummary>("site_id"),
+                filter.site_id.clone().map(Into::into),
+            ),
+            (
+                field::<AuditSummary>("page_id"),
+                filter.page_id.clone().map(Into::into),
+            ),
+            (
+                field::<AuditSummary>("audit_profile_id"),
+                filter.audit_profile_id.clone().map(Into::into),
+            ),
+        ])
+    }
+
+    fn page_key(&self) -> Option<(&str, &str)> {
+        Some((&self.page_id, &self.audit_profile_id))
+    }
+
+    fn recency(&self) -> Option<&str> {
+        Some(&self.fetch_time)
+    }
+}
+
+impl Record for AuditDetail {
+    const COLLECTION: &'static str = collections::AUDIT_DETAILS;
+    const SCHEMA_VERSION: i32 = migrations::AUDIT_DETAIL_SCHEMA_VERSION;
+
+    fn migrations() -> Vec<Migration> {
+        migrations::audit_detail_migrations()
+    }
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        matches_optional(&filter.site_id, &self.site_id)
+            && matches_optional(&filter.page_id, &self.page_id)
+            && matches_optional(&filter.audit_profile_id, &self.audit_profile_id)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        filter_document(&[
+            (
+                field::<AuditDetail>("site_id"),
+                filter.site_id.clone().map(Into::into),
+            ),
+            (
+                field::<AuditDetail>("page_id"),
+                filter.page_id.clone().map(Into::into),
+            ),
+            (
+                field::<AuditDetail>("audit_profile_id"),
+                filter.audit_profile_id.clone().map(Into::into),
+            ),
+        ])
+    }
+
+    fn page_key(&self) -> Option<(&str, &str)> {
+        match (&self.page_id, &self.audit_profile_id) {
+            (Some(page_id), Some(audit_profile_id)) => Some((page_id, audit_profile_id)),
+            _ => None,
+        }
+    }
+
+    fn recency(&self) -> Option<&str> {
+        Some(&self.fetch_time)
+    }
+}
+
+/// Runs belong to a site as a whole, so page and profile filters are ignored.
+impl Record for SiteRun {
+    const COLLECTION: &'static str = collections::SITE_RUNS;
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        matches_value(&filter.site_id, &self.site_id)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        filter_document(&[(
+            field::<SiteRun>("site_id"),
+            filter.site_id.clone().map(Into::into),
+        )])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bson::doc;
+
+    fn site(name: &str) -> Site {
+        let mut site = Site::default();
+        site.set_name(name.to_owned());
+        site
+    }
+
+    #[test]
+    fn meta_site_projection_selects_only_its_fields() {
+        assert_eq!(
+            <MetaSite as Projection<Site>>::projection(),
+            doc! { "_id": 1, "name": 1, "groupId": 1 }
+        );
+    }
+
+    #[test]
+    fn project_reads_meta_sites_from_sites() {
+        let repository = InMemoryRepository::new();
+        let home = repository.insert(site("home")).unwrap();
+        repository.insert(site("docs")).unwrap();
+
+        let meta_sites: Vec<MetaSite> = repository
+            .project(&RecordFilter::for_site(home.clone()))
+            .unwrap();
+
+        assert_eq!(meta_sites.len(), 1);
+        assert_eq!(meta_sites[0].id(), &home);
+        assert_eq!(meta_sites[0].name(), "home");
+    }
+
+    #[test]
+    fn filter_documents_use_serialized_field_names() {
+        let site_id = ObjectId::new();
+        let filter = RecordFilter::for_profile(site_id.clone(), "home".into(), "mobile".into());
+        let expected = doc! {
+            "siteId": site_id.clone(),
+            "pageId": "home",
+            "auditProfileId": "mobile",
+        };
+
+        assert_eq!(AuditSummary::filter_document(&filter), expected);
+        assert_eq!(AuditDetail::filter_document(&filter), expected);
+        assert_eq!(
+            SiteRun::filter_document(&filter),
+            doc! { "siteId": site_id }
+        );
+    }
+
+    #[test]
+    fn insert_rejects_a_duplicate_id() {
+        let repository = InMemoryRepository::new();
+        let id = repository.insert(site("home")).unwrap();
+        let mut duplicate = site("docs");
+        duplicate.set_id(Some(id.clone()));
+
+        match repository.insert(duplicate) {
+            Err(RepositoryError::DuplicateId(duplicate_id)) => assert_eq!(duplicate_id, id),
+            other => panic!("expected a duplicate id error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use super::{Record, RecordFilter, Repository, RepositoryError};
+use crate::collections::IndexDefinition;
+use bson::oid::ObjectId;
+use std::sync::Mutex;
+
+/// Keeps records in insertion order behind a lock; meant for tests and local
+/// tooling rather than production use.
+#[derive(Debug, Default)]
+pub struct InMemoryRepository<T> {
+    records: Mutex<Vec<T>>,
+    indexes: Mutex<Vec<IndexDefinition>>,
+}
+
+impl<T: Record> InMemoryRepository<T> {
+    pub fn new() -> InMemoryRepository<T> {
+        InMemoryRepository {
+            records: Mutex::new(Vec::new()),
+            indexes: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Indexes requested through `ensure_index`; they are recorded but not
+    /// enforced.
+    pub fn indexes(&self) -> Vec<IndexDefinition> {
+        self.indexes.lock().unwrap().clone()
+    }
+}
+
+impl<T: Record> Repository<T> for InMemoryRepository<T> {
+    fn insert(&self, mut record: T) -> Result<ObjectId, RepositoryError> {
+        let id = match record.record_id() {
+            Some(id) => id.clone(),
+            None => {
+                let id = ObjectId::new();
+                record.assign_id(id.clone());
+                id
+            }
+        };
+
+        let mut records = self.records.lock().unwrap();
+        if records.iter().any(|r| r.record_id() == Some(&id)) {
+            return Err(RepositoryError::DuplicateId(id));
+        }
+        records.push(record);
+
+        Ok(id)
+    }
+
+    fn get(&self, id: &ObjectId) -> Result<Option<T>, RepositoryError> {
+        let records = self.records.lock().unwrap();
+        Ok(records.iter().find(|r| r.record_id() == Some(id)).cloned())
+    }
+
+    fn list(&self, filter: &RecordFilter) -> Result<Vec<T>, RepositoryError> {
+        let records = self.records.lock().unwrap();
+        Ok(records
+            .iter()
+            .filter(|r| r.matches(filter))
+            .cloned()
+            .collect())
+    }
+
+    fn delete(&self, id: &ObjectId) -> Result<bool, RepositoryError> {
+        let mut records = self.records.lock().unwrap();
+        let count = records.len();
+        records.retain(|r| r.record_id() != Some(id));
+        Ok(records.len() != count)
+    }
+
+    fn ensure_index(&self, index: &IndexDefinition) -> Result<(), RepositoryError> {
+        let mut indexes = self.indexes.lock().unwrap();
+        if !indexes.contains(index) {
+            indexes.push(index.clone());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Projection, Record, RecordFilter, Repository, RepositoryError};
+use crate::collections::IndexDefinition;
+use crate::migrations;
+use bson::{doc, oid::ObjectId};
+use mongodb::error::{Error, ErrorKind, WriteFailure};
+use mongodb::options::FindOptions;
+use mongodb::sync::{Collection, Database};
+use std::marker::PhantomData;
+
+pub struct MongoRepository<T> {
+    database: Database,
+    collection: Collection,
+    record: PhantomData<T>,
+}
+
+impl<T: Record> MongoRepository<T> {
+    pub fn new(database: &Database) -> MongoRepository<T> {
+        MongoRepository::with_collection_name(database, T::COLLECTION)
+    }
+
+    pub fn with_collection_name(database: &Database, collection_name: &str) -> MongoRepository<T> {
+        MongoRepository {
+            database: database.clone(),
+            collection: database.collection(collection_name),
+            record: PhantomData,
+        }
+    }
+
+    pub fn collection(&self) -> &Collection {
+        &self.collection
+    }
+}
+
+impl<T: Record> Repository<T> for MongoRepository<T> {
+    fn insert(&self, mut record: T) -> Result<ObjectId, RepositoryError> {
+        let id = match record.record_id() {
+            Some(id) => id.clone(),
+            None => {
+                let id = ObjectId::new();
+                record.assign_id(id.clone());
+                id
+            }
+        };
+
+        match self
+            .collection
+            .insert_one(bson::to_document(&record)?, None)
+        {
+            Ok(_) => Ok(id),
+            Err(e) if is_duplicate_key(&e) => Err(RepositoryError::DuplicateId(id)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn get(&self, id: &ObjectId) -> Result<Option<T>, RepositoryError> {
+        match self.collection.find_one(doc! { "_id": id.clone() }, None)? {
+            Some(document) => Ok(Some(migrations::read(document)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn list(&self, filter: &RecordFilter) -> Result<Vec<T>, RepositoryError> {
+        let mut records = Vec::new();
+        for document in self.collection.find(T::filter_document(filter), None)? {
+            records.push(migrations::read(document?)?);
+        }
+
+        Ok(records)
+    }
+
+    fn project<P: Projection<T>>(&self, filter: &RecordFilter) -> Result<Vec<P>, RepositoryError> {
+        let options = FindOptions::builder()
+            .projection(Some(P::projection()))
+            .build();
+
+        let mut projections = Vec::new();
+        for document in self.collection.find(T::filter_document(filter), options)? {
+            projections.push(bson::from_document(document?)?);
+        }
+
+        Ok(projections)
+    }
+
+    fn delete(&self, id: &ObjectId) -> Result<bool, RepositoryError> {
+        let result = self
+            .collection
+            .delete_one(doc! { "_id": id.clone() }, None)?;
+
+        Ok(result.deleted_count > 0)
+    }
+
+    fn ensure_index(&self, index: &IndexDefinition) -> Result<(), RepositoryError> {
+        self.database.run_command(
+            doc! {
+                "createIndexes": self.collection.name(),
+                "indexes": [index.to_document()],
+            },
+            None,
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Server error code for a unique index violation, such as a reused `_id`.
+const DUPLICATE_KEY: i32 = 11000;
+
+fn is_duplicate_key(error: &Error) -> bool {
+    match error.kind.as_ref() {
+        ErrorKind::WriteError(WriteFailure::WriteError(e)) => e.code == DUPLICATE_KEY,
+        _ => false,
+    }
+}
@@ -0,0 +1,429 @@
+use crate::metrics::{self, Metric};
+use crate::queries::AuditSummaryField;
+use crate::AuditSummary;
+use bson::{doc, oid::ObjectId, Bson, Document};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use getset::Getters;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const PERCENTILES: [(&str, f64); 3] = [("p50", 50.0), ("p75", 75.0), ("p95", 95.0)];
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum Interval {
+    Day,
+    Week,
+    Month,
+}
+
+impl Interval {
+    /// First day of the bucket holding `time`; weeks start on Monday.
+    pub fn bucket_start(&self, time: DateTime<Utc>) -> NaiveDate {
+        let date = time.naive_utc().date();
+        match self {
+            Interval::Day => date,
+            Interval::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+            Interval::Month => date.with_day(1).expect("every month has a first day"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, Copy, PartialEq)]
+#[getset(get = "pub")]
+pub struct Percentiles {
+    p50: f64,
+    p75: f64,
+    p95: f64,
+}
+
+impl Percentiles {
+    pub fn from_values(values: &[f64]) -> Option<Percentiles> {
+        Some(Percentiles {
+            p50: metrics::percentile(values, 50.0)?,
+            p75: metrics::percentile(values, 75.0)?,
+            p95: metrics::percentile(values, 95.0)?,
+        })
+    }
+}
+
+/// Statistics for one page and audit profile of a site over one bucket.
+/// `bucket` is the first day of the bucket as `YYYY-MM-DD`.
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct Rollup {
+    site_id: ObjectId,
+    page_id: String,
+    audit_profile_id: String,
+    interval: Interval,
+    bucket: String,
+    count: i64,
+    metrics: BTreeMap<Metric, Percentiles>,
+}
+
+/// Groups summaries by site, page, audit profile and bucket, ordered by the
+/// same. Summaries with an unparseable fetch time are skipped.
+pub fn rollup(summaries: &[AuditSummary], interval: Interval) -> Vec<Rollup> {
+    let mut buckets: BTreeMap<(&ObjectId, &str, &str, NaiveDate), Vec<&AuditSummary>> =
+        BTreeMap::new();
+    for summary in summaries {
+        let fetch_time = match DateTime::parse_from_rfc3339(summary.fetch_time()) {
+            Ok(fetch_time) => fetch_time.with_timezone(&Utc),
+            Err(_) => continue,
+        };
+        buckets
+            .entry((
+                summary.site_id(),
+                summary.page_id(),
+                summary.audit_profile_id(),
+                interval.bucket_start(fetch_time),
+            ))
+            .or_default()
+            .push(summary);
+    }
+
+    buckets
+        .into_iter()
+        .map(
+            |((site_id, page_id, audit_profile_id, bucket), summaries)| {
+                let metrics = Metric::all()
+                    .iter()
+                    .filter_map(|metric| {
+                        let values: Vec<f64> =
+                            summaries.iter().filter_map(|s| metric.value(s)).collect();
+                        Percentiles::from_values(&values).map(|p| (*metric, p))
+                    })
+                    .collect();
+
+                Rollup {
+                    site_id: site_id.clone(),
+                    page_id: page_id.to_owned(),
+                    audit_profile_id: audit_profile_id.to_owned(),
+                    interval,
+                    bucket: bucket.format("%Y-%m-%d").to_string(),
+                    count: summaries.len() as i64,
+                    metrics,
+                }
+            },
+        )
+        .collect()
+}
+
+/// An `aggregate` pipeline over `auditSummaries` whose output deserializes
+/// into `Rollup` and matches `rollup` for the summaries selected by
+/// `filter`. Uses `$sortArray`, so needs MongoDB 5.2 or later.
+pub fn rollup_pipeline(filter: Document, interval: Interval) -> Vec<Document> {
+    let fetch_time = format!("${}", AuditSummaryField::FetchTime.path());
+    let bucket = match interval {
+        Interval::Day => doc! { "$dateToString": { "format": "%Y-%m-%d", "date": "$_date" } },
+        Interval::Week => doc! {
+            "$dateToString": {
+                "format": "%Y-%m-%d",
+                "date": {
+                    "$subtract": [
+                        "$_date",
+                        { "$multiply": [{ "$subtract": [{ "$isoDayOfWeek": "$_date" }, 1] }, 86_400_000] },
+                    ]
+                },
+            }
+        },
+        Interval::Month => doc! { "$dateToString": { "format": "%Y-%m-01", "date": "$_date" } },
+    };
+
+    let mut group = doc! {
+        "_id": {
+            "siteId": format!("${}", AuditSummaryField::SiteId.path()),
+            "pageId": format!("${}", AuditSummaryField::PageId.path()),
+            "auditProfileId": format!("${}", AuditSummaryField::AuditProfileId.path()),
+            "bucket": "$_bucket",
+        },
+        "count": { "$sum": 1 },
+    };
+    let mut metrics = Document::new();
+    for metric in Metric::all().iter() {
+        let key = metric_key(*metric);
+        group.insert(key.clone(), doc! { "$push": format!("${}", metric.path()) });
+        metrics.insert(key.clone(), percentiles_expression(&format!("${}", key)));
+    }
+
+    vec![
+        doc! { "$match": filter },
+        doc! { "$addFields": { "_date": { "$dateFromString": { "dateString": fetch_time, "onError": Bson::Null } } } },
+        doc! { "$match": { "_date": { "$ne": Bson::Null } } },
+        doc! { "$addFields": { "_bucket": bucket } },
+        doc! { "$group": group },
+        doc! {
+            "$project": {
+                "_id": 0,
+                "siteId": "$_id.siteId",
+                "pageId": "$_id.pageId",
+                "auditProfileId": "$_id.auditProfileId",
+                "interval": { "$literal": enum_bson(&interval) },
+                "bucket": "$_id.bucket",
+                "count": 1,
+                "metrics": metrics,
+            }
+        },
+        doc! { "$sort": { "siteId": 1, "pageId": 1, "auditProfileId": 1, "bucket": 1 } },
+    ]
+}
+
+fn metric_key(metric: Metric) -> String {
+    match enum_bson(&metric) {
+        Bson::String(key) => key,
+        _ => unreachable!("unit variants serialize as strings"),
+    }
+}
+
+fn enum_bson<T: Serialize>(value: &T) -> Bson {
+    bson::to_bson(value).expect("enum serializes to bson")
+}
+
+/// Linearly interpolated percentiles of the non-null values in `array`, or
+/// `$$REMOVE` when there are none.
+fn percentiles_expression(array: &str) -> Bson {
+    let mut percentiles = Document::new();
+    for (name, percentile) in PERCENTILES.iter() {
+        percentiles.insert(*name, percentile_expression("$$sorted", *percentile));
+    }
+
+    Bson::Document(doc! {
+        "$let": {
+            "vars": {
+                "sorted": {
+                    "$sortArray": {
+                        "input": { "$filter": { "input": array, "cond": { "$ne": ["$$this", Bson::Null] } } },
+                        "sortBy": 1,
+                    }
+                }
+            },
+            "in": {
+                "$cond": [
+                    { "$eq": [{ "$size": "$$sorted" }, 0] },
+                    "$$REMOVE",
+                    percentiles,
+                ]
+            },
+        }
+    })
+}
+
+fn percentile_expression(sorted: &str, percentile: f64) -> Document {
+    doc! {
+        "$let": {
+            "vars": {
+                "rank": { "$multiply": [percentile / 100.0, { "$subtract": [{ "$size": sorted }, 1] }] }
+            },
+            "in": {
+                "$let": {
+                    "vars": {
+                        "lower": { "$arrayElemAt": [sorted, { "$toInt": { "$floor": "$$rank" } }] },
+                        "upper": { "$arrayElemAt": [sorted, { "$toInt": { "$ceil": "$$rank" } }] },
+                    },
+                    "in": {
+                        "$add": [
+                            "$$lower",
+                            {
+                                "$multiply": [
+                                    { "$subtract": ["$$upper", "$$lower"] },
+                                    { "$subtract": ["$$rank", { "$floor": "$$rank" }] },
+                                ]
+                            },
+                        ]
+                    },
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{set_metric, summary};
+    use chrono::TimeZone;
+
+    fn summaries(site: &ObjectId, other_site: &ObjectId) -> Vec<AuditSummary> {
+        let mut summaries = vec![
+            summary(site, "2021-03-01T08:00:00.000Z", 0.8),
+            summary(site, "2021-03-02T08:00:00.000Z", 0.9),
+            summary(site, "2021-03-03T08:00:00.000Z", 0.7),
+            summary(site, "2021-03-08T08:00:00.000Z", 0.6),
+            summary(other_site, "2021-03-02T09:00:00.000Z", 0.5),
+            summary(other_site, "2021-03-02T10:00:00.000Z", 0.3),
+            summary(site, "yesterday", 0.1),
+        ];
+        for (i, summary) in summaries.iter_mut().enumerate() {
+            set_metric(summary, Metric::SpeedIndex, 1000.0 * (i + 1) as f64);
+        }
+        summaries
+    }
+
+    fn key(rollup: &Rollup) -> (&ObjectId, &str, i64) {
+        (rollup.site_id(), rollup.bucket(), *rollup.count())
+    }
+
+    #[test]
+    fn buckets_start_on_the_first_day() {
+        let time = Utc.with_ymd_and_hms(2021, 3, 10, 23, 59, 0).unwrap();
+
+        assert_eq!(Interval::Day.bucket_start(time).to_string(), "2021-03-10");
+        assert_eq!(Interval::Week.bucket_start(time).to_string(), "2021-03-08");
+        assert_eq!(Interval::Month.bucket_start(time).to_string(), "2021-03-01");
+    }
+
+    #[test]
+    fn rollup_groups_by_site_as_well_as_page() {
+        let site = ObjectId::new();
+        let other_site = ObjectId::new();
+
+        let rollups = rollup(&summaries(&site, &other_site), Interval::Week);
+        let keys: Vec<_> = rollups.iter().map(key).collect();
+
+        let mut expected = vec![
+            (&site, "2021-03-01", 3),
+            (&site, "2021-03-08", 1),
+            (&other_site, "2021-03-01", 2),
+        ];
+        expected.sort();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn rollup_computes_percentiles_per_bucket() {
+        let site = ObjectId::new();
+        let other_site = ObjectId::new();
+
+        let rollups = rollup(&summaries(&site, &other_site), Interval::Day);
+        let other = rollups.iter().find(|r| r.site_id() == &other_site).unwrap();
+
+        assert_eq!(*other.count(), 2);
+        let score = other.metrics()[&Metric::Score];
+        assert_eq!(*score.p50(), 0.4);
+        assert_eq!(*other.metrics()[&Metric::SpeedIndex].p50(), 5500.0);
+        assert_eq!(*other.metrics()[&Metric::SpeedIndex].p95(), 5950.0);
+    }
+
+    /// The value at a `$`-prefixed field path, or null.
+    fn resolve(document: &Document, path: &str) -> Bson {
+        let mut value = Bson::Document(document.clone());
+        for key in path.trim_start_matches('$').split('.') {
+            value = match value {
+                Bson::Document(document) => document.get(key).cloned().unwrap_or(Bson::Null),
+                _ => Bson::Null,
+            };
+        }
+        value
+    }
+
+    fn stage<'a>(pipeline: &'a [Document], name: &str) -> &'a Document {
+        pipeline
+            .iter()
+            .find_map(|stage| stage.get_document(name).ok())
+            .unwrap()
+    }
+
+    /// Runs the `$group` and `$project` stages over the summaries. The date
+    /// and percentile expressions need a server, so buckets come from
+    /// `Interval::bucket_start` and percentiles from `Percentiles`; every
+    /// field path is resolved as MongoDB would.
+    fn run_pipeline(summaries: &[AuditSummary], interval: Interval) -> Vec<Rollup> {
+        let pipeline = rollup_pipeline(Document::new(), interval);
+        let group = stage(&pipeline, "$group");
+        let project = stage(&pipeline, "$project");
+        let group_id = group.get_document("_id").unwrap();
+
+        let mut groups: Vec<(Document, Vec<Document>)> = Vec::new();
+        for summary in summaries {
+            let fetch_time = match DateTime::parse_from_rfc3339(summary.fetch_time()) {
+                Ok(fetch_time) => fetch_time.with_timezone(&Utc),
+                Err(_) => continue,
+            };
+            let mut document = bson::to_document(summary).unwrap();
+            let bucket = interval.bucket_start(fetch_time).format("%Y-%m-%d");
+            document.insert("_bucket", bucket.to_string());
+
+            let id: Document = group_id
+                .iter()
+                .map(|(key, path)| (key.clone(), resolve(&document, path.as_str().unwrap())))
+                .collect();
+            match groups.iter_mut().find(|(group, _)| *group == id) {
+                Some((_, documents)) => documents.push(document),
+                None => groups.push((id, vec![document])),
+            }
+        }
+
+        let mut rollups: Vec<Rollup> = groups
+            .into_iter()
+            .map(|(id, documents)| {
+                let mut grouped = doc! { "_id": id, "count": documents.len() as i32 };
+                let mut metrics = Document::new();
+                for metric in Metric::all().iter() {
+                    let key = metric_key(*metric);
+                    let path = group.get_document(&key).unwrap().get_str("$push").unwrap();
+                    let values: Vec<f64> = documents
+                        .iter()
+                        .filter_map(|document| resolve(document, path).as_f64())
+                        .collect();
+                    if let Some(percentiles) = Percentiles::from_values(&values) {
+                        metrics.insert(key, bson::to_bson(&percentiles).unwrap());
+                    }
+                }
+                grouped.insert("metrics", metrics);
+
+                let mut output = Document::new();
+                for (key, value) in project {
+                    let value = match value {
+                        Bson::String(path) => resolve(&grouped, path),
+                        Bson::Document(expression) if expression.contains_key("$literal") => {
+                            expression.get("$literal").unwrap().clone()
+                        }
+                        Bson::Int32(0) => continue,
+                        _ => grouped.get(key).unwrap().clone(),
+                    };
+                    output.insert(key.clone(), value);
+                }
+                bson::from_document(output).unwrap()
+            })
+            .collect();
+        rollups.sort_by(|a, b| {
+            (a.site_id(), a.page_id(), a.audit_profile_id(), a.bucket()).cmp(&(
+                b.site_id(),
+                b.page_id(),
+                b.audit_profile_id(),
+                b.bucket(),
+            ))
+        });
+        rollups
+    }
+
+    #[test]
+    fn pipeline_matches_rollup_on_the_same_summaries() {
+        let site = ObjectId::new();
+        let other_site = ObjectId::new();
+        let summaries = summaries(&site, &other_site);
+
+        for interval in &[Interval::Day, Interval::Week, Interval::Month] {
+            assert_eq!(
+                run_pipeline(&summaries, *interval),
+                rollup(&summaries, *interval),
+                "{:?}",
+                interval
+            );
+        }
+    }
+
+    #[test]
+    fn pipeline_groups_and_sorts_by_site() {
+        let pipeline = rollup_pipeline(Document::new(), Interval::Day);
+        let group_id = stage(&pipeline, "$group").get_document("_id").unwrap();
+        let sort = stage(&pipeline, "$sort");
+
+        assert_eq!(group_id.get_str("siteId").unwrap(), "$siteId");
+        assert_eq!(
+            sort.keys().collect::<Vec<_>>(),
+            vec!["siteId", "pageId", "auditProfileId", "bucket"]
+        );
+    }
+}
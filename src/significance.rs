@@ -0,0 +1,339 @@
+use crate::lh_models::{AllAttemptReports, Report};
+use crate::metrics::{self, Metric};
+use crate::regression::Verdict;
+use getset::{Getters, Setters};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, Copy, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct ComparisonConfig {
+    /// Confidence level of the interval; a p-value below `1 - confidence`
+    /// is significant.
+    confidence: f64,
+    resamples: usize,
+    /// Seed for the bootstrap, so the same inputs give the same interval.
+    seed: u64,
+}
+
+impl Default for ComparisonConfig {
+    fn default() -> ComparisonConfig {
+        ComparisonConfig {
+            confidence: 0.95,
+            resamples: 2000,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, Copy, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct MannWhitney {
+    /// U statistic of the `after` sample.
+    u: f64,
+    z: f64,
+    /// Two-sided.
+    p_value: f64,
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, Copy, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct ConfidenceInterval {
+    lower: f64,
+    upper: f64,
+    confidence: f64,
+}
+
+impl ConfidenceInterval {
+    pub fn contains(&self, value: f64) -> bool {
+        self.lower <= value && value <= self.upper
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct MetricComparison {
+    metric: Metric,
+    before_count: usize,
+    after_count: usize,
+    before_median: f64,
+    after_median: f64,
+    /// `after_median - before_median`.
+    median_difference: f64,
+    test: MannWhitney,
+    interval: ConfidenceInterval,
+    verdict: Verdict,
+}
+
+/// Compares attempts before and after a change, per metric. Metrics missing
+/// from either set are left out.
+pub fn compare_reports(
+    before: &[Report],
+    after: &[Report],
+    config: &ComparisonConfig,
+) -> Vec<MetricComparison> {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+
+    Metric::all()
+        .iter()
+        .filter_map(|metric| {
+            let before: Vec<f64> = before
+                .iter()
+                .filter_map(|r| metric.report_value(r))
+                .collect();
+            let after: Vec<f64> = after
+                .iter()
+                .filter_map(|r| metric.report_value(r))
+                .collect();
+            compare_metric(*metric, &before, &after, config, &mut rng)
+        })
+        .collect()
+}
+
+pub fn compare_attempts(
+    before: &AllAttemptReports,
+    after: &AllAttemptReports,
+    config: &ComparisonConfig,
+) -> Vec<MetricComparison> {
+    compare_reports(before.reports(), after.reports(), config)
+}
+
+fn compare_metric<R: Rng>(
+    metric: Metric,
+    before: &[f64],
+    after: &[f64],
+    config: &ComparisonConfig,
+    rng: &mut R,
+) -> Option<MetricComparison> {
+    let before = &finite(before)[..];
+    let after = &finite(after)[..];
+    let before_median = metrics::median(before)?;
+    let after_median = metrics::median(after)?;
+    let test = mann_whitney_u(before, after)?;
+    let interval =
+        bootstrap_median_difference(before, after, config.resamples, config.confidence, rng)?;
+
+    let median_difference = after_median - before_median;
+    let verdict = if test.p_value >= 1.0 - config.confidence || interval.contains(0.0) {
+        Verdict::Unchanged
+    } else if (median_difference > 0.0) == metric.higher_is_better() {
+        Verdict::Improved
+    } else {
+        Verdict::Regressed
+    };
+
+    Some(MetricComparison {
+        metric,
+        before_count: before.len(),
+        after_count: after.len(),
+        before_median,
+        after_median,
+        median_difference,
+        test,
+        interval,
+        verdict,
+    })
+}
+
+/// Two-sided Mann-Whitney U test using the normal approximation with tie
+/// and continuity corrections, which is reasonable from about eight values
+/// per sample. NaN and infinite values are left out, as they cannot be
+/// ranked.
+pub fn mann_whitney_u(before: &[f64], after: &[f64]) -> Option<MannWhitney> {
+    let before = finite(before);
+    let after = finite(after);
+    if before.is_empty() || after.is_empty() {
+        return None;
+    }
+
+    let mut values: Vec<(f64, bool)> = before
+        .iter()
+        .map(|v| (*v, false))
+        .chain(after.iter().map(|v| (*v, true)))
+        .collect();
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let n = values.len() as f64;
+    let mut after_rank_sum = 0.0;
+    let mut tie_term = 0.0;
+    let mut i = 0;
+    while i < values.len() {
+        let mut j = i;
+        while j + 1 < values.len() && values[j + 1].0 == values[i].0 {
+            j += 1;
+        }
+        // Tied values share the mean of their 1-based ranks.
+        let rank = (i + j) as f64 / 2.0 + 1.0;
+        let ties = (j - i + 1) as f64;
+        after_rank_sum += rank * values[i..=j].iter().filter(|v| v.1).count() as f64;
+        tie_term += ties * ties * ties - ties;
+        i = j + 1;
+    }
+
+    let n_before = before.len() as f64;
+    let n_after = after.len() as f64;
+    let u = after_rank_sum - n_after * (n_after + 1.0) / 2.0;
+    let mean = n_before * n_after / 2.0;
+    let variance = n_before * n_after / 12.0 * ((n + 1.0) - tie_term / (n * (n - 1.0)));
+
+    let (z, p_value) = if variance > 0.0 {
+        let difference = u - mean;
+        let corrected = (difference.abs() - 0.5).max(0.0) * difference.signum();
+        let z = corrected / variance.sqrt();
+        (z, erfc(z.abs() / std::f64::consts::SQRT_2).min(1.0))
+    } else {
+        (0.0, 1.0)
+    };
+
+    Some(MannWhitney { u, z, p_value })
+}
+
+fn finite(values: &[f64]) -> Vec<f64> {
+    values.iter().copied().filter(|v| v.is_finite()).collect()
+}
+
+/// Percentile bootstrap interval of `median(after) - median(before)`.
+pub fn bootstrap_median_difference<R: Rng>(
+    before: &[f64],
+    after: &[f64],
+    resamples: usize,
+    confidence: f64,
+    rng: &mut R,
+) -> Option<ConfidenceInterval> {
+    if before.is_empty() || after.is_empty() || resamples == 0 {
+        return None;
+    }
+
+    let mut resample = |values: &[f64], buffer: &mut Vec<f64>| {
+        buffer.clear();
+        buffer.extend((0..values.len()).map(|_| values[rng.gen_range(0..values.len())]));
+        metrics::median(buffer)
+    };
+    let mut buffer = Vec::with_capacity(before.len().max(after.len()));
+    let mut differences = Vec::with_capacity(resamples);
+    for _ in 0..resamples {
+        let before_median = resample(before, &mut buffer)?;
+        let after_median = resample(after, &mut buffer)?;
+        differences.push(after_median - before_median);
+    }
+
+    let tail = (1.0 - confidence) / 2.0 * 100.0;
+    Some(ConfidenceInterval {
+        lower: metrics::percentile(&differences, tail)?,
+        upper: metrics::percentile(&differences, 100.0 - tail)?,
+        confidence,
+    })
+}
+
+/// Complementary error function, accurate to about 1.2e-7.
+fn erfc(x: f64) -> f64 {
+    let t = 1.0 / (1.0 + 0.5 * x.abs());
+    let polynomial = -x * x - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77))))))));
+    let result = t * polynomial.exp();
+
+    if x >= 0.0 {
+        result
+    } else {
+        2.0 - result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The Hollander & Wolfe example from R's `wilcox.test` documentation,
+    /// which has ties in `after`. R reports W = 58 for `before`, so U for
+    /// `after` is 9 * 9 - 58 = 23, and p = 0.1329 with `exact = FALSE`.
+    const BEFORE: [f64; 9] = [1.83, 0.50, 1.62, 2.48, 1.68, 1.88, 1.55, 3.06, 1.30];
+    #[allow(clippy::approx_constant)] // 3.14 is a measurement, not pi.
+    const AFTER: [f64; 9] = [0.878, 0.647, 0.598, 2.05, 1.06, 1.29, 1.06, 3.14, 1.29];
+
+    fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+        assert!(
+            (actual - expected).abs() < tolerance,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn mann_whitney_u_of_a_textbook_sample() {
+        let test = mann_whitney_u(&BEFORE, &AFTER).unwrap();
+
+        assert_eq!(*test.u(), 23.0);
+        assert_close(*test.z(), -1.502_688, 1e-6);
+        assert_close(*test.p_value(), 0.132_919, 1e-6);
+    }
+
+    #[test]
+    fn mann_whitney_u_ignores_non_finite_values() {
+        let mut before = BEFORE.to_vec();
+        before.extend_from_slice(&[f64::NAN, f64::INFINITY]);
+        let mut after = AFTER.to_vec();
+        after.push(f64::NEG_INFINITY);
+
+        assert_eq!(
+            mann_whitney_u(&before, &after),
+            mann_whitney_u(&BEFORE, &AFTER)
+        );
+        assert_eq!(mann_whitney_u(&[f64::NAN], &AFTER), None);
+    }
+
+    #[test]
+    fn identical_samples_are_not_significant() {
+        let test = mann_whitney_u(&BEFORE, &BEFORE).unwrap();
+
+        assert_eq!(*test.z(), 0.0);
+        assert_eq!(*test.p_value(), 1.0);
+    }
+
+    #[test]
+    fn bootstrap_is_reproducible_for_a_seed() {
+        let interval = |seed| {
+            bootstrap_median_difference(
+                &BEFORE,
+                &AFTER,
+                500,
+                0.95,
+                &mut StdRng::seed_from_u64(seed),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(interval(7), interval(7));
+        assert!(interval(7).lower() <= interval(7).upper());
+    }
+
+    #[test]
+    fn separated_samples_are_a_regression() {
+        let before: Vec<f64> = (0..10).map(|i| 3000.0 + f64::from(i)).collect();
+        let after: Vec<f64> = (0..10).map(|i| 3500.0 + f64::from(i)).collect();
+        let config = ComparisonConfig::default();
+        let mut rng = StdRng::seed_from_u64(*config.seed());
+
+        let comparison =
+            compare_metric(Metric::SpeedIndex, &before, &after, &config, &mut rng).unwrap();
+
+        assert_eq!(*comparison.median_difference(), 500.0);
+        assert!(!comparison.interval().contains(0.0));
+        assert_eq!(*comparison.verdict(), Verdict::Regressed);
+
+        let comparison =
+            compare_metric(Metric::SpeedIndex, &before, &before, &config, &mut rng).unwrap();
+        assert_eq!(*comparison.verdict(), Verdict::Unchanged);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::budgets::Budget;
+use crate::{AuditProfile, Authentication, Page};
+use bson::{doc, oid::ObjectId, Bson, Document};
+use getset::Getters;
+use std::convert::TryFrom;
+use std::fmt;
+
+const LAST_RUN_ID: &str = "lastRunId";
+const NAME: &str = "name";
+const GROUP_ID: &str = "groupId";
+const PAGES: &str = "pages";
+const AUDIT_PROFILES: &str = "auditProfiles";
+const AUTHENTICATION: &str = "authentication";
+const BUDGETS: &str = "budgets";
+
+pub fn site_filter(site_id: &ObjectId) -> Document {
+    doc! { "_id": site_id.clone() }
+}
+
+/// Atomically increments `Site::last_run_id` and returns the new value, so
+/// concurrent schedulers never hand out the same run id.
+#[derive(Debug, Getters, Clone)]
+#[getset(get = "pub")]
+pub struct RunIdAllocation {
+    filter: Document,
+    update: Document,
+    projection: Document,
+}
+
+impl RunIdAllocation {
+    pub fn for_site(site_id: &ObjectId) -> RunIdAllocation {
+        RunIdAllocation {
+            filter: site_filter(site_id),
+            update: doc! { "$inc": { LAST_RUN_ID: 1 } },
+            projection: doc! { LAST_RUN_ID: 1 },
+        }
+    }
+
+    /// The equivalent `findAndModify` database command, returning the
+    /// updated document.
+    pub fn to_command(&self, collection: &str) -> Document {
+        doc! {
+            "findAndModify": collection,
+            "query": self.filter.clone(),
+            "update": self.update.clone(),
+            "fields": self.projection.clone(),
+            "new": true,
+            "upsert": false,
+        }
+    }
+
+    /// Reads the allocated run id from either the updated site document or
+    /// the raw `findAndModify` command reply.
+    pub fn run_id_from(result: &Document) -> Option<i32> {
+        let site = match result.get_document("value") {
+            Ok(value) => value,
+            Err(_) => result,
+        };
+
+        match site.get(LAST_RUN_ID) {
+            Some(Bson::Int32(run_id)) => Some(*run_id),
+            Some(Bson::Int64(run_id)) => i32::try_from(*run_id).ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SiteChange {
+    Name(String),
+    GroupId(Option<ObjectId>),
+    Pages(Vec<Page>),
+    AddPage(Page),
+    RemovePage(String),
+    AuditProfiles(Vec<AuditProfile>),
+    AddAuditProfile(AuditProfile),
+    RemoveAuditProfile(String),
+    Authentication(Option<Authentication>),
+    Budgets(Option<Vec<Budget>>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SiteUpdateError {
+    /// The changes would replace, push to or pull from the same array in
+    /// more than one way, which MongoDB rejects as a path conflict.
+    Conflict(&'static str),
+}
+
+impl fmt::Display for SiteUpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SiteUpdateError::Conflict(field) => {
+                write!(f, "conflicting changes to {} in one update", field)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SiteUpdateError {}
+
+/// Builds a single update document for the given changes.
+///
+/// MongoDB rejects an update that touches the same array with more than one
+/// of `$set`, `$push` and `$pull`, so replacing, adding and removing pages
+/// (or audit profiles) cannot be mixed; such changes return
+/// `SiteUpdateError::Conflict` and must be sent as separate updates.
+/// Several adds, or several removes, of the same array are fine.
+pub fn site_update(changes: &[SiteChange]) -> Result<Document, SiteUpdateError> {
+    let mut set = Document::new();
+    let mut unset = Document::new();
+    let mut push = Document::new();
+    let mut pull = Document::new();
+    let mut added_pages = Vec::new();
+    let mut removed_page_ids = Vec::new();
+    let mut added_audit_profiles = Vec::new();
+    let mut removed_audit_profile_ids = Vec::new();
+
+    for change in changes {
+        match change {
+            SiteChange::Name(name) => {
+                set.insert(NAME, name.clone());
+            }
+            SiteChange::GroupId(group_id) => {
+                set.insert(GROUP_ID, to_bson(group_id));
+            }
+            SiteChange::Pages(pages) => {
+                set.insert(PAGES, to_bson(pages));
+            }
+            SiteChange::AddPage(page) => added_pages.push(to_bson(page)),
+            SiteChange::RemovePage(page_id) => removed_page_ids.push(Bson::from(page_id.clone())),
+            SiteChange::AuditProfiles(audit_profiles) => {
+                set.insert(AUDIT_PROFILES, to_bson(audit_profiles));
+            }
+            SiteChange::AddAuditProfile(audit_profile) => {
+                added_audit_profiles.push(to_bson(audit_profile))
+            }
+            SiteChange::RemoveAuditProfile(audit_profile_id) => {
+                removed_audit_profile_ids.push(Bson::from(audit_profile_id.clone()))
+            }
+            SiteChange::Authentication(Some(authentication)) => {
+                set.insert(AUTHENTICATION, to_bson(authentication));
+            }
+            SiteChange::Authentication(None) => {
+                unset.insert(AUTHENTICATION, "");
+            }
+            SiteChange::Budgets(Some(budgets)) => {
+                set.insert(BUDGETS, to_bson(budgets));
+            }
+            SiteChange::Budgets(None) => {
+                unset.insert(BUDGETS, "");
+            }
+        }
+    }
+
+    for field in [PAGES, AUDIT_PROFILES].iter() {
+        let (added, removed) = if *field == PAGES {
+            (&added_pages, &removed_page_ids)
+        } else {
+            (&added_audit_profiles, &removed_audit_profile_ids)
+        };
+        let operators = [
+            set.contains_key(field),
+            !added.is_empty(),
+            !removed.is_empty(),
+        ];
+        if operators.iter().filter(|used| **used).count() > 1 {
+            return Err(SiteUpdateError::Conflict(field));
+        }
+    }
+
+    if !added_pages.is_empty() {
+        push.insert(PAGES, doc! { "$each": added_pages });
+    }
+    if !added_audit_profiles.is_empty() {
+        push.insert(AUDIT_PROFILES, doc! { "$each": added_audit_profiles });
+    }
+    if !removed_page_ids.is_empty() {
+        pull.insert(PAGES, doc! { "id": { "$in": removed_page_ids } });
+    }
+    if !removed_audit_profile_ids.is_empty() {
+        pull.insert(
+            AUDIT_PROFILES,
+            doc! { "id": { "$in": removed_audit_profile_ids } },
+        );
+    }
+
+    let mut update = Document::new();
+    for (operator, fields) in [
+        ("$set", set),
+        ("$unset", unset),
+        ("$push", push),
+        ("$pull", pull),
+    ] {
+        if !fields.is_empty() {
+            update.insert(operator, fields);
+        }
+    }
+
+    Ok(update)
+}
+
+fn to_bson<T: serde::Serialize>(value: &T) -> Bson {
+    bson::to_bson(value).expect("site fields are valid bson")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(id: &str) -> Page {
+        let mut page = Page::default();
+        page.set_id(id.to_owned());
+        page.set_url(format!("https://example.com/{}", id));
+        page
+    }
+
+    #[test]
+    fn adds_and_removes_of_one_array_conflict() {
+        let changes = [
+            SiteChange::AddPage(page("a")),
+            SiteChange::RemovePage("b".to_owned()),
+        ];
+
+        assert_eq!(site_update(&changes), Err(SiteUpdateError::Conflict(PAGES)));
+    }
+
+    #[test]
+    fn replacing_and_adding_audit_profiles_conflict() {
+        let profile = AuditProfile::new(
+            "mobile".to_owned(),
+            "Mobile".to_owned(),
+            "mobile".to_owned(),
+            "7.0.0".to_owned(),
+        );
+        let changes = [
+            SiteChange::AuditProfiles(vec![profile.clone()]),
+            SiteChange::AddAuditProfile(profile),
+        ];
+
+        assert_eq!(
+            site_update(&changes),
+            Err(SiteUpdateError::Conflict(AUDIT_PROFILES))
+        );
+    }
+
+    #[test]
+    fn changes_to_different_arrays_are_combined() {
+        let changes = [
+            SiteChange::Name("Example".to_owned()),
+            SiteChange::AddPage(page("a")),
+            SiteChange::AddPage(page("b")),
+            SiteChange::RemoveAuditProfile("desktop".to_owned()),
+        ];
+        let update = site_update(&changes).unwrap();
+
+        assert_eq!(
+            update.get_document("$set").unwrap(),
+            &doc! { NAME: "Example" }
+        );
+        assert_eq!(
+            update
+                .get_document("$push")
+                .unwrap()
+                .get_document(PAGES)
+                .unwrap()
+                .get_array("$each")
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            update.get_document("$pull").unwrap(),
+            &doc! { AUDIT_PROFILES: { "id": { "$in": ["desktop"] } } }
+        );
+    }
+
+    #[test]
+    fn run_id_out_of_range_is_none() {
+        let reply = doc! { "value": { LAST_RUN_ID: i64::from(i32::MAX) + 1 } };
+        assert_eq!(RunIdAllocation::run_id_from(&reply), None);
+        assert_eq!(
+            RunIdAllocation::run_id_from(&doc! { LAST_RUN_ID: 7_i64 }),
+            Some(7)
+        );
+    }
+}
@@ -0,0 +1,363 @@
+use crate::collections;
+use crate::metrics::{self, Metric};
+use crate::repository::{self, Record, RecordFilter, Scoped};
+use crate::AuditSummary;
+use bson::{oid::ObjectId, Document};
+use chrono::{DateTime, Duration, Utc};
+use getset::{Getters, Setters};
+use serde::{Deserialize, Serialize};
+
+/// A service-level objective: at least `target` of the runs in the last
+/// `window_days` keep `metric` at or better than `threshold`, or, with a
+/// `percentile`, that percentile of the window is at or better than it.
+///
+/// Without a page or audit profile the objective covers all pages or
+/// profiles of the site; `device` narrows it to profiles for that device.
+#[derive(Deserialize, Serialize, Debug, Getters, Setters, Clone, PartialEq)]
+#[getset(get = "pub", set = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct Slo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "_id")]
+    id: Option<ObjectId>,
+    site_id: ObjectId,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    audit_profile_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    device: Option<String>,
+    metric: Metric,
+    threshold: f64,
+    /// Fraction of runs, e.g. 0.95. Unused with a `percentile`.
+    target: f64,
+    window_days: i64,
+    /// Percentile of the window, e.g. 75, that must meet the threshold
+    /// instead of the fraction of good runs meeting the target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentile: Option<f64>,
+}
+
+impl Slo {
+    pub fn new(
+        site_id: ObjectId,
+        name: String,
+        metric: Metric,
+        threshold: f64,
+        target: f64,
+        window_days: i64,
+    ) -> Slo {
+        Slo {
+            id: None,
+            site_id,
+            name,
+            page_id: None,
+            audit_profile_id: None,
+            device: None,
+            metric,
+            threshold,
+            target,
+            window_days,
+            percentile: None,
+        }
+    }
+
+    pub fn applies_to(&self, summary: &AuditSummary) -> bool {
+        summary.site_id() == &self.site_id
+            && self
+                .page_id
+                .as_ref()
+                .is_none_or(|id| id == summary.page_id())
+            && self
+                .audit_profile_id
+                .as_ref()
+                .is_none_or(|id| id == summary.audit_profile_id())
+            && self
+                .device
+                .as_ref()
+                .is_none_or(|device| device == summary.audit_profile().device())
+    }
+
+    pub fn is_good(&self, value: f64) -> bool {
+        if self.metric.higher_is_better() {
+            value >= self.threshold
+        } else {
+            value <= self.threshold
+        }
+    }
+
+    /// The start of the window ending at `now`, or the earliest
+    /// representable time for a window reaching past it.
+    pub fn window_start(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        Duration::try_days(self.window_days)
+            .and_then(|window| now.checked_sub_signed(window))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    /// Fraction of good runs the objective requires. With a `percentile`
+    /// that is the share of runs on the good side of it: 75 requires 0.75
+    /// of the runs for lower-is-better metrics and 0.25 otherwise.
+    pub fn good_fraction(&self) -> f64 {
+        match self.percentile {
+            Some(percentile) if self.metric.higher_is_better() => {
+                1.0 - percentile.clamp(0.0, 100.0) / 100.0
+            }
+            Some(percentile) => percentile.clamp(0.0, 100.0) / 100.0,
+            None => self.target,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct SloStatus {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slo_id: Option<ObjectId>,
+    window_start: bson::DateTime,
+    window_end: bson::DateTime,
+    total_runs: i64,
+    good_runs: i64,
+    /// Fraction of good runs; 1 when there are no runs.
+    compliance: f64,
+    met: bool,
+    /// Bad runs the objective allows over the window so far.
+    error_budget: f64,
+    /// Allowed bad runs left; negative once the budget is exhausted.
+    error_budget_remaining: f64,
+    /// `error_budget_remaining / error_budget`, or 0 for a zero budget.
+    error_budget_remaining_ratio: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    percentile_value: Option<f64>,
+}
+
+impl SloStatus {
+    pub fn is_budget_exhausted(&self) -> bool {
+        self.error_budget_remaining < 0.0
+    }
+}
+
+/// Evaluates the objective over the summaries it applies to that were
+/// fetched within its window ending at `now`.
+pub fn evaluate(slo: &Slo, summaries: &[AuditSummary], now: DateTime<Utc>) -> SloStatus {
+    let window_start = slo.window_start(now);
+    let values: Vec<f64> = summaries
+        .iter()
+        .filter(|summary| slo.applies_to(summary))
+        .filter(|summary| {
+            DateTime::parse_from_rfc3339(summary.fetch_time())
+                .map(|fetch_time| fetch_time >= window_start && fetch_time <= now)
+                .unwrap_or(false)
+        })
+        .filter_map(|summary| slo.metric.value(summary))
+        .collect();
+
+    let total_runs = values.len() as i64;
+    let good_runs = values.iter().filter(|value| slo.is_good(**value)).count() as i64;
+    let bad_runs = (total_runs - good_runs) as f64;
+    let compliance = if total_runs > 0 {
+        good_runs as f64 / total_runs as f64
+    } else {
+        1.0
+    };
+    let error_budget = (1.0 - slo.good_fraction()) * total_runs as f64;
+    let error_budget_remaining = error_budget - bad_runs;
+    let error_budget_remaining_ratio = if error_budget > 0.0 {
+        error_budget_remaining / error_budget
+    } else {
+        0.0
+    };
+    let percentile_value = slo
+        .percentile
+        .and_then(|percentile| metrics::percentile(&values, percentile));
+    let met = match (slo.percentile, percentile_value) {
+        (Some(_), Some(value)) => slo.is_good(value),
+        // No runs in the window.
+        (Some(_), None) => true,
+        (None, _) => compliance >= slo.target,
+    };
+
+    SloStatus {
+        slo_id: slo.id.clone(),
+        window_start: bson::DateTime(window_start),
+        window_end: bson::DateTime(now),
+        total_runs,
+        good_runs,
+        compliance,
+        met,
+        error_budget,
+        error_budget_remaining,
+        error_budget_remaining_ratio,
+        percentile_value,
+    }
+}
+
+impl Record for Slo {
+    const COLLECTION: &'static str = collections::SLOS;
+
+    fn record_id(&self) -> Option<&ObjectId> {
+        self.id.as_ref()
+    }
+
+    fn assign_id(&mut self, id: ObjectId) {
+        self.id = Some(id);
+    }
+
+    fn matches(&self, filter: &RecordFilter) -> bool {
+        repository::scope_matches(self, filter)
+    }
+
+    fn filter_document(filter: &RecordFilter) -> Document {
+        repository::scope_filter_document::<Slo>(filter)
+    }
+}
+
+impl Scoped for Slo {
+    fn scope(&self) -> (&ObjectId, Option<&str>, Option<&str>) {
+        (
+            &self.site_id,
+            self.page_id.as_deref(),
+            self.audit_profile_id.as_deref(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::{set_metric, summary};
+    use chrono::TimeZone;
+
+    fn fixture() -> (Slo, Vec<AuditSummary>, DateTime<Utc>) {
+        let site_id = ObjectId::new();
+        let slo = Slo::new(
+            site_id.clone(),
+            "Score".to_owned(),
+            Metric::Score,
+            0.9,
+            0.75,
+            7,
+        );
+        let summaries = [0.95, 0.92, 0.85, 0.8]
+            .iter()
+            .enumerate()
+            .map(|(day, score)| {
+                summary(&site_id, &format!("2021-03-0{}T12:00:00Z", day + 1), *score)
+            })
+            .collect();
+        (
+            slo,
+            summaries,
+            Utc.with_ymd_and_hms(2021, 3, 7, 0, 0, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn compliance_mode_compares_good_runs_with_the_target() {
+        let (slo, summaries, now) = fixture();
+
+        let status = evaluate(&slo, &summaries, now);
+
+        assert_eq!(status.total_runs(), &4);
+        assert_eq!(status.good_runs(), &2);
+        assert_eq!(status.compliance(), &0.5);
+        assert!(!status.met());
+        assert_eq!(status.percentile_value(), &None);
+    }
+
+    #[test]
+    fn percentile_mode_compares_the_percentile_with_the_threshold() {
+        let (mut slo, summaries, now) = fixture();
+        slo.set_percentile(Some(75.0));
+
+        let status = evaluate(&slo, &summaries, now);
+        assert!((status.percentile_value().unwrap() - 0.9275).abs() < 1e-9);
+        assert!(status.met());
+
+        slo.set_percentile(Some(25.0));
+        assert!(!evaluate(&slo, &summaries, now).met());
+    }
+
+    #[test]
+    fn percentile_mode_error_budget_follows_the_percentile() {
+        let (mut slo, summaries, now) = fixture();
+        // The target alone would leave one bad run of budget.
+        slo.set_target(0.75);
+        slo.set_percentile(Some(75.0));
+
+        // Two of four scores are at least 0.9; p75 needs a quarter of them.
+        let status = evaluate(&slo, &summaries, now);
+        assert!(status.met());
+        assert_eq!(status.error_budget(), &3.0);
+        assert_eq!(status.error_budget_remaining(), &1.0);
+        assert!(!status.is_budget_exhausted());
+
+        // p25 needs three quarters of them.
+        slo.set_percentile(Some(25.0));
+        let status = evaluate(&slo, &summaries, now);
+        assert!(!status.met());
+        assert_eq!(status.error_budget(), &1.0);
+        assert!(status.is_budget_exhausted());
+    }
+
+    #[test]
+    fn window_start_saturates_for_huge_windows() {
+        let (mut slo, summaries, now) = fixture();
+        slo.set_window_days(i64::MAX);
+
+        assert_eq!(slo.window_start(now), DateTime::<Utc>::MIN_UTC);
+        assert_eq!(evaluate(&slo, &summaries, now).total_runs(), &4);
+    }
+
+    #[test]
+    fn percentile_mode_respects_lower_is_better_metrics() {
+        let (mut slo, summaries, now) = fixture();
+        let site_id = slo.site_id().clone();
+        let summaries: Vec<AuditSummary> = summaries
+            .into_iter()
+            .map(|mut summary| {
+                let speed_index = (1.0 - summary.categories().performance().score()) * 20000.0;
+                set_metric(&mut summary, Metric::SpeedIndex, speed_index);
+                summary
+            })
+            .collect();
+        slo = Slo::new(
+            site_id,
+            "SI".to_owned(),
+            Metric::SpeedIndex,
+            3400.0,
+            0.75,
+            7,
+        );
+        slo.set_percentile(Some(75.0));
+
+        // Speed indexes of 1000, 1600, 3000 and 4000 ms; p75 is 3250 ms.
+        let status = evaluate(&slo, &summaries, now);
+        assert!(status.met());
+        assert_eq!(status.error_budget_remaining(), &0.0);
+
+        slo.set_threshold(3000.0);
+        assert!(!evaluate(&slo, &summaries, now).met());
+    }
+
+    #[test]
+    fn scope_filters_use_serialized_field_names() {
+        let (mut slo, _, _) = fixture();
+        slo.set_page_id(Some("home".to_owned()));
+        let filter = RecordFilter::for_page(slo.site_id().clone(), "home".to_owned());
+
+        assert!(slo.matches(&filter));
+        assert!(!slo.matches(&RecordFilter::for_page(
+            slo.site_id().clone(),
+            "blog".to_owned()
+        )));
+        assert_eq!(
+            Slo::filter_document(&filter),
+            bson::doc! {
+                "siteId": slo.site_id().clone(),
+                "pageId": { "$in": ["home", bson::Bson::Null] },
+            }
+        );
+    }
+}
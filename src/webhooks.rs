@@ -0,0 +1,329 @@
+use crate::regression::RegressionReport;
+use crate::{AuditSummary, SiteRun, SiteRunStatus};
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use getset::Getters;
+use hmac::{Hmac, Mac, NewMac};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+
+/// Version of the event payload; bumped on incompatible changes.
+pub const EVENT_VERSION: i32 = 1;
+
+/// Header carrying `t=<unix seconds>,v1=<hex signature>`.
+pub const SIGNATURE_HEADER: &str = "Slick-Signature";
+
+/// How far a signature's timestamp may be from the receiver's clock.
+pub const DEFAULT_TOLERANCE_SECONDS: i64 = 300;
+
+#[derive(Deserialize, Serialize, Debug, Getters, Clone, PartialEq)]
+#[getset(get = "pub")]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEvent {
+    /// Unique per event, so receivers can drop redeliveries.
+    id: String,
+    version: i32,
+    /// RFC 3339.
+    created_at: String,
+    #[serde(flatten)]
+    data: WebhookEventData,
+}
+
+/// Ids are hex strings rather than BSON object ids to keep the JSON plain.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum WebhookEventData {
+    #[serde(rename_all = "camelCase")]
+    RunStarted {
+        site_id: String,
+        run_id: i32,
+        started_at: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    PageCompleted {
+        site_id: String,
+        run_id: i32,
+        page_id: String,
+        audit_profile_id: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        audit_summary_id: Option<String>,
+        score: f64,
+    },
+    #[serde(rename_all = "camelCase")]
+    RunCompleted {
+        site_id: String,
+        run_id: i32,
+        status: SiteRunStatus,
+        started_at: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        completed_at: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    RegressionDetected {
+        site_id: String,
+        run_id: i32,
+        page_id: String,
+        audit_profile_id: String,
+        report: RegressionReport,
+    },
+}
+
+impl WebhookEvent {
+    pub fn new(data: WebhookEventData, now: DateTime<Utc>) -> WebhookEvent {
+        WebhookEvent {
+            id: hex::encode(rand::thread_rng().gen::<[u8; 16]>()),
+            version: EVENT_VERSION,
+            created_at: rfc3339(now),
+            data,
+        }
+    }
+
+    pub fn run_started(site_run: &SiteRun, now: DateTime<Utc>) -> WebhookEvent {
+        WebhookEvent::new(
+            WebhookEventData::RunStarted {
+                site_id: site_run.site_id().to_hex(),
+                run_id: *site_run.run_id(),
+                started_at: rfc3339(site_run.started_at().0),
+            },
+            now,
+        )
+    }
+
+    pub fn page_completed(summary: &AuditSummary, now: DateTime<Utc>) -> WebhookEvent {
+        WebhookEvent::new(
+            WebhookEventData::PageCompleted {
+                site_id: summary.site_id().to_hex(),
+                run_id: *summary.site_run_id(),
+                page_id: summary.page_id().clone(),
+                audit_profile_id: summary.audit_profile_id().clone(),
+                audit_summary_id: summary.id().as_ref().map(|id| id.to_hex()),
+                score: *summary.categories().performance().score(),
+            },
+            now,
+        )
+    }
+
+    pub fn run_completed(site_run: &SiteRun, now: DateTime<Utc>) -> WebhookEvent {
+        WebhookEvent::new(
+            WebhookEventData::RunCompleted {
+                site_id: site_run.site_id().to_hex(),
+                run_id: *site_run.run_id(),
+                status: *site_run.status(),
+                started_at: rfc3339(site_run.started_at().0),
+                completed_at: site_run.completed_at().map(|date| rfc3339(date.0)),
+            },
+            now,
+        )
+    }
+
+    pub fn regression_detected(
+        summary: &AuditSummary,
+        report: RegressionReport,
+        now: DateTime<Utc>,
+    ) -> WebhookEvent {
+        WebhookEvent::new(
+            WebhookEventData::RegressionDetected {
+                site_id: summary.site_id().to_hex(),
+                run_id: *summary.site_run_id(),
+                page_id: summary.page_id().clone(),
+                audit_profile_id: summary.audit_profile_id().clone(),
+                report,
+            },
+            now,
+        )
+    }
+
+    pub fn to_json(&self) -> Result<String, WebhookError> {
+        serde_json::to_string(self).map_err(WebhookError::Json)
+    }
+
+    /// Parses a payload, rejecting versions newer than this crate knows.
+    pub fn from_json(payload: &[u8]) -> Result<WebhookEvent, WebhookError> {
+        let event: WebhookEvent = serde_json::from_slice(payload).map_err(WebhookError::Json)?;
+        if event.version > EVENT_VERSION {
+            return Err(WebhookError::UnsupportedVersion(event.version));
+        }
+        Ok(event)
+    }
+
+    /// Serializes and signs the event, returning the body and the
+    /// `Slick-Signature` header value.
+    pub fn signed(
+        &self,
+        secret: &[u8],
+        now: DateTime<Utc>,
+    ) -> Result<(String, String), WebhookError> {
+        let body = self.to_json()?;
+        let signature = sign(secret, now.timestamp(), body.as_bytes());
+        Ok((body, signature))
+    }
+}
+
+#[derive(Debug)]
+pub enum WebhookError {
+    Json(serde_json::Error),
+    MalformedSignature,
+    SignatureMismatch,
+    /// The signature's timestamp is outside the tolerance, as for a replay.
+    StaleTimestamp(i64),
+    UnsupportedVersion(i32),
+}
+
+impl fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebhookError::Json(e) => write!(f, "invalid webhook payload: {}", e),
+            WebhookError::MalformedSignature => write!(f, "malformed {} header", SIGNATURE_HEADER),
+            WebhookError::SignatureMismatch => write!(f, "webhook signature does not match"),
+            WebhookError::StaleTimestamp(timestamp) => {
+                write!(
+                    f,
+                    "webhook timestamp {} is outside the tolerance",
+                    timestamp
+                )
+            }
+            WebhookError::UnsupportedVersion(version) => {
+                write!(f, "unsupported webhook event version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+/// The `Slick-Signature` header value for a payload: an HMAC-SHA256 of
+/// `<timestamp>.<payload>`.
+pub fn sign(secret: &[u8], timestamp: i64, payload: &[u8]) -> String {
+    let signature = hex::encode(mac(secret, timestamp, payload).finalize().into_bytes());
+    format!("t={},v1={}", timestamp, signature)
+}
+
+/// Checks a `Slick-Signature` header against the payload. Any `v1` entry may
+/// match, which allows signing with an old and a new secret while rotating.
+pub fn verify(
+    secret: &[u8],
+    header: &str,
+    payload: &[u8],
+    now: DateTime<Utc>,
+    tolerance: Duration,
+) -> Result<(), WebhookError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => {
+                timestamp = Some(
+                    value
+                        .parse::<i64>()
+                        .map_err(|_| WebhookError::MalformedSignature)?,
+                )
+            }
+            Some(("v1", value)) => {
+                signatures.push(hex::decode(value).map_err(|_| WebhookError::MalformedSignature)?)
+            }
+            _ => {}
+        }
+    }
+    let timestamp = timestamp.ok_or(WebhookError::MalformedSignature)?;
+    if signatures.is_empty() {
+        return Err(WebhookError::MalformedSignature);
+    }
+
+    let age = now
+        .timestamp()
+        .checked_sub(timestamp)
+        .and_then(i64::checked_abs);
+    if age.is_none_or(|age| age > tolerance.num_seconds()) {
+        return Err(WebhookError::StaleTimestamp(timestamp));
+    }
+
+    let matched = signatures
+        .iter()
+        .any(|signature| mac(secret, timestamp, payload).verify(signature).is_ok());
+    if matched {
+        Ok(())
+    } else {
+        Err(WebhookError::SignatureMismatch)
+    }
+}
+
+/// Verifies the signature with the default tolerance and parses the event.
+pub fn verify_event(
+    secret: &[u8],
+    header: &str,
+    payload: &[u8],
+    now: DateTime<Utc>,
+) -> Result<WebhookEvent, WebhookError> {
+    verify(
+        secret,
+        header,
+        payload,
+        now,
+        Duration::seconds(DEFAULT_TOLERANCE_SECONDS),
+    )?;
+    WebhookEvent::from_json(payload)
+}
+
+fn mac(secret: &[u8], timestamp: i64, payload: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+    mac
+}
+
+fn rfc3339(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const SECRET: &[u8] = b"whsec_test";
+    const PAYLOAD: &[u8] = br#"{"type":"runStarted"}"#;
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2021, 3, 1, 12, 0, 0).unwrap()
+    }
+
+    fn tolerance() -> Duration {
+        Duration::seconds(DEFAULT_TOLERANCE_SECONDS)
+    }
+
+    #[test]
+    fn accepts_a_fresh_signature() {
+        let header = sign(SECRET, now().timestamp() - 10, PAYLOAD);
+
+        assert!(verify(SECRET, &header, PAYLOAD, now(), tolerance()).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_stale_or_tampered_signature() {
+        let stale = now().timestamp() - DEFAULT_TOLERANCE_SECONDS - 1;
+        let header = sign(SECRET, stale, PAYLOAD);
+        assert!(matches!(
+            verify(SECRET, &header, PAYLOAD, now(), tolerance()),
+            Err(WebhookError::StaleTimestamp(t)) if t == stale
+        ));
+
+        let header = sign(SECRET, now().timestamp(), PAYLOAD);
+        assert!(matches!(
+            verify(SECRET, &header, b"{}", now(), tolerance()),
+            Err(WebhookError::SignatureMismatch)
+        ));
+    }
+
+    #[test]
+    fn rejects_timestamps_that_overflow_the_age() {
+        for timestamp in [i64::MIN, i64::MIN + 1, i64::MAX].iter() {
+            let header = format!("t={},v1=00", timestamp);
+            assert!(matches!(
+                verify(SECRET, &header, PAYLOAD, now(), tolerance()),
+                Err(WebhookError::StaleTimestamp(t)) if t == *timestamp
+            ));
+        }
+    }
+}
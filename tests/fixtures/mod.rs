@@ -0,0 +1,39 @@
+//! Builders over the JSON fixtures in this directory, shared by the unit
+//! tests under `src/`.
+
+use crate::metrics::Metric;
+use crate::AuditSummary;
+use bson::oid::ObjectId;
+
+/// `audit_summary.json`: a Lighthouse 6 mobile run of the home page.
+pub(crate) fn audit_summary() -> AuditSummary {
+    serde_json::from_str(include_str!("audit_summary.json")).unwrap()
+}
+
+/// The fixture summary with a fresh id, moved to another site, time and
+/// performance score.
+pub(crate) fn summary(site_id: &ObjectId, fetch_time: &str, score: f64) -> AuditSummary {
+    let mut summary = audit_summary();
+    summary.set_id(Some(ObjectId::new()));
+    summary.set_site_id(site_id.clone());
+    summary.set_fetch_time(fetch_time.to_owned());
+    set_metric(&mut summary, Metric::Score, score);
+    summary
+}
+
+/// Sets a metric through its document path, so any metric can be changed
+/// without a setter per web vital.
+pub(crate) fn set_metric(summary: &mut AuditSummary, metric: Metric, value: f64) {
+    let mut document = bson::to_document(summary).unwrap();
+    let path = metric.path();
+    let mut keys: Vec<&str> = path.split('.').collect();
+    let field = keys.pop().unwrap();
+
+    let mut parent = &mut document;
+    for key in keys {
+        parent = parent.get_document_mut(key).unwrap();
+    }
+    parent.insert(field, value);
+
+    *summary = bson::from_document(document).unwrap();
+}